@@ -1,15 +1,48 @@
 // branch.rs
 pub const MAP_SIZE_POW2: usize = 20;
 pub const BRANCHES_SIZE: usize = 1 << MAP_SIZE_POW2;
+// the bounds of --map-size, the pass draws the edge ids below the max and the
+// runtime masks them to the chosen size
+pub const MAP_SIZE_POW2_MIN: usize = 16;
+pub const MAP_SIZE_POW2_MAX: usize = 24;
 pub const ENABLE_RANDOM_LEN: bool = false;
 pub const ENABLE_MICRO_RANDOM_LEN: bool = true;
 pub const TMOUT_SKIP: usize = 3;
-pub const TIME_LIMIT: u64 = 1;
+pub const TIME_LIMIT: u64 = 1000; // ms, upper bound for calibration
+pub const TIME_LIMIT_MIN: u64 = 20; // ms
 pub const MEM_LIMIT: u64 = 200; // MB
-pub const TIME_LIMIT_TRACK: u64 = 90;
+pub const TIME_LIMIT_TRACK: u64 = 120000; // ms, upper bound for calibration
+pub const TIME_LIMIT_TRACK_MIN: u64 = 1000; // ms
+// ms, on top of the calibrated track time, the tracked child waits for the solver
+pub const TRACK_SOLVE_GRACE: u64 = 10000;
 pub const MEM_LIMIT_TRACK: u64 = 0;
+pub const CGROUP_PIDS_MAX: u64 = 256;
+pub const TRACK_KILL_GRACE: u64 = 1000; // ms, between SIGTERM and SIGKILL
+pub const MAX_SOLVE_TIME: u64 = 90; // s, per tracked seed
+pub const TRACK_RETRIES: usize = 2;
+// times a seed whose solving hit the cap goes back into the queue
+pub const MAX_REQUEUE: usize = 2;
+pub const CHECKPOINT_INTERVAL: u64 = 300; // s
+pub const STATS_INTERVAL: u64 = 5; // s, between writes of fuzzer_stats and plot_data
+pub const TUI_INTERVAL: u64 = 1; // s, between redraws of the status screen
+// the table shared by --coordinate instances, in slots
+pub const COORD_BRANCH_SLOTS: usize = 1 << 20;
+pub const COORD_SEED_SLOTS: usize = 1 << 18;
+pub const COORD_MAX_PROBES: usize = 64;
+// s, longer than tracking a seed with its retries, then the claim is taken over
+pub const COORD_CLAIM_LEASE: u32 = 1800;
+pub const COORD_CLAIM_RETRY: u64 = 60; // s, between claims of a seed another instance holds
+pub const SUMMARY_INTERVAL: u64 = 60; // s, between summaries when stdout is not a terminal
+pub const INPUT_CACHE_SIZE: usize = 64 << 20; // bytes of queue inputs kept in memory
 
-pub const MAX_INVARIABLE_NUM: usize = 16;
+// calibrate.rs
+pub const CALIBRATE_RUNS: usize = 4;
+pub const CALIBRATE_TRACK_SEEDS: usize = 8;
+pub const CALIBRATE_PERCENTILE: usize = 95;
+pub const TMOUT_MULT: u64 = 5;
+pub const TMOUT_TRACK_MULT: u64 = 10;
+
+pub const MAX_INVARIABLE_NUM: usize = 16; // runs of an input once some edge varies
 pub const MAX_INPUT_LEN: usize = 1000000;
 pub const SAVING_WHOLE: bool = false;
 pub const USE_CODECACHE: bool = true;
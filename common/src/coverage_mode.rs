@@ -0,0 +1,110 @@
+// How the fast binary turns the path into map indices, see countEdge in
+// TaintPass.cc. Each block stores
+//   prev = (prev >> shift) ^ (ctx & ctx_mask) ^ (cur >> 1)
+// and hits the edge prev ^ cur. Block ids have 24 bits, so with a shift of 24
+// only the last block is left in prev, and smaller shifts keep a few more.
+
+use crate::defs;
+use std::{fmt, fs, io, path::Path, str::FromStr};
+
+pub const NGRAM_MIN: u32 = 3;
+// a shift that keeps exactly n - 1 blocks exists up to here
+pub const NGRAM_MAX: u32 = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoverageMode {
+    // (previous block, block), like AFL
+    Edge,
+    // edges in their calling context, like Angora
+    Context,
+    // the last n blocks
+    Ngram(u32),
+}
+
+impl Default for CoverageMode {
+    fn default() -> Self {
+        CoverageMode::Context
+    }
+}
+
+impl CoverageMode {
+    pub fn ctx_mask(&self) -> u32 {
+        match self {
+            CoverageMode::Context => !0,
+            _ => 0,
+        }
+    }
+
+    // (cur >> 1) has 23 bits, it should be gone after n - 1 shifts and
+    // not before.
+    pub fn prev_shift(&self) -> u32 {
+        match self {
+            CoverageMode::Ngram(n) => (23 + n - 2) / (n - 1),
+            _ => 24,
+        }
+    }
+
+    // Recorded in the output directory, so that resuming uses the same mode.
+    pub fn load(out_dir: &Path) -> io::Result<Option<Self>> {
+        match fs::read_to_string(out_dir.join(defs::COVERAGE_MODE_FILE)) {
+            Ok(s) => s
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, out_dir: &Path) -> io::Result<()> {
+        fs::write(out_dir.join(defs::COVERAGE_MODE_FILE), format!("{}\n", self))
+    }
+}
+
+impl FromStr for CoverageMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "edge" => Ok(CoverageMode::Edge),
+            "context" => Ok(CoverageMode::Context),
+            _ => match s.strip_prefix("ngram-").and_then(|n| n.parse::<u32>().ok()) {
+                Some(n) if n >= NGRAM_MIN && n <= NGRAM_MAX => Ok(CoverageMode::Ngram(n)),
+                _ => Err("unknown coverage mode"),
+            },
+        }
+    }
+}
+
+impl fmt::Display for CoverageMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoverageMode::Edge => write!(f, "edge"),
+            CoverageMode::Context => write!(f, "context"),
+            CoverageMode::Ngram(n) => write!(f, "ngram-{}", n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage_mode() {
+        for s in &["edge", "context", "ngram-3", "ngram-7"] {
+            assert_eq!(s.parse::<CoverageMode>().unwrap().to_string(), *s);
+        }
+        assert!("ngram-2".parse::<CoverageMode>().is_err());
+        assert!("ngram-8".parse::<CoverageMode>().is_err());
+        assert!("ctx".parse::<CoverageMode>().is_err());
+
+        assert_eq!(CoverageMode::Edge.prev_shift(), 24);
+        for n in NGRAM_MIN..=NGRAM_MAX {
+            // the (n - 2)th older block keeps a bit, the (n - 1)th none
+            let s = CoverageMode::Ngram(n).prev_shift();
+            assert!(s * (n - 2) < 23 && s * (n - 1) >= 23);
+        }
+    }
+}
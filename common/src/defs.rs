@@ -7,6 +7,8 @@ pub static ANGORA_BIN_DIR: &str = "ANGORA_BIN_DIR";
 pub static TRACK_OUTPUT_VAR: &str = "ANGORA_TRACK_OUTPUT";
 pub static COND_STMT_ENV_VAR: &str = "ANGORA_COND_STMT_SHM_ID";
 pub static BRANCHES_SHM_ENV_VAR: &str = "ANGORA_BRANCHES_SHM_ID";
+pub static MAP_SIZE_ENV_VAR: &str = "ANGORA_MAP_SIZE";
+pub static COVERAGE_MODE_ENV_VAR: &str = "ANGORA_COVERAGE_MODE";
 pub static LD_LIBRARY_PATH_VAR: &str = "LD_LIBRARY_PATH";
 pub static ASAN_OPTIONS_VAR: &str = "ASAN_OPTIONS";
 pub static MSAN_OPTIONS_VAR: &str = "MSAN_OPTIONS";
@@ -19,7 +21,13 @@ pub static MSAN_OPTIONS_CONTENT: &str =
 // depot.rs
 pub static CRASHES_DIR: &str = "crashes";
 pub static HANGS_DIR: &str = "hangs";
+pub static OOMS_DIR: &str = "ooms";
 pub static INPUTS_DIR: &str = "queue";
+pub static TRACK_FAILURES_DIR: &str = "track_failures";
+pub static META_DIR: &str = "meta";
+pub static SYNCED_DIR: &str = ".synced";
+// in the sync directory, hidden from AFL
+pub static COORD_FILE: &str = ".fastgen_coord";
 
 // forksrv.rs
 pub static ENABLE_FORKSRV: &str = "ANGORA_ENABLE_FORKSRV";
@@ -30,6 +38,14 @@ pub static ANGORA_DIR_NAME: &str = "angora";
 pub static ANGORA_LOG_FILE: &str = "angora.log";
 pub static COND_QUEUE_FILE: &str = "cond_queue.csv";
 pub static CHART_STAT_FILE: &str = "chart_stat.json";
+pub static TRACK_STAT_FILE: &str = "track_stat.csv";
+pub static CHECKPOINT_FILE: &str = "checkpoint";
+pub static FUZZER_STATS_FILE: &str = "fuzzer_stats";
+pub static PLOT_DATA_FILE: &str = "plot_data";
+pub static BRANCH_STAT_FILE: &str = "branch_stat.csv";
+pub static TIMELINE_FILE: &str = "coverage_timeline.csv";
+pub static COVERAGE_MODE_FILE: &str = "coverage_mode";
+pub static MAP_SIZE_FILE: &str = "map_size";
 
 // tmpfs.rs
 pub static PERSIST_TRACK_FILES: &str = "ANGORA_DISABLE_TMPFS";
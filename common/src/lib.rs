@@ -1,4 +1,5 @@
 pub mod config;
+pub mod coverage_mode;
 pub mod defs;
 pub mod shm;
 pub mod cond_stmt_base;
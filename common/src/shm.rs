@@ -13,7 +13,10 @@ pub struct SHM<T: Sized> {
 
 impl<T> SHM<T> {
     pub fn new() -> Self {
-        let size = std::mem::size_of::<T>() as usize;
+        Self::with_size(std::mem::size_of::<T>() as usize)
+    }
+
+    fn with_size(size: usize) -> Self {
         let id = unsafe {
             libc::shmget(
                 libc::IPC_PRIVATE,
@@ -51,7 +54,21 @@ impl<T> SHM<T> {
     pub fn is_fail(&self) -> bool {
         -1 == self.ptr as isize
     }
+}
+
+// A byte buffer whose size is only known at runtime, e.g. the branch map.
+impl SHM<u8> {
+    pub fn new_bytes(size: usize) -> Self {
+        Self::with_size(size)
+    }
 
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.size) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.size) }
+    }
 }
 
 impl<T> Deref for SHM<T> {
@@ -100,6 +117,15 @@ mod tests {
         assert_eq!(33, sl[4]);
     }
 
+    #[test]
+    fn test_bytes() {
+        let mut buf = SHM::<u8>::new_bytes(1 << 16);
+        buf.clear();
+        assert_eq!(buf.as_slice().len(), 1 << 16);
+        buf.as_mut_slice()[65535] = 7;
+        assert_eq!(buf.as_slice()[65535], 7);
+    }
+
     #[test]
     fn test_shm_fail() {
         let arr = SHM::<[u8; 10]>::from_id(88888888);
@@ -124,5 +124,18 @@ extern "C" {
     delete (struct Session *)session;
   }
 
+  // the state shared by all sessions, for checkpoints
+  uint32_t filter_map_size() {
+    return kMapSize;
+  }
+  void get_filter_state(uint8_t *trace, uint8_t *context) {
+    memcpy(trace, trace_map_, kMapSize);
+    memcpy(context, context_map_, kMapSize);
+  }
+  void set_filter_state(const uint8_t *trace, const uint8_t *context) {
+    memcpy(trace_map_, trace, kMapSize);
+    memcpy(context_map_, context, kMapSize);
+  }
+
 };
 
@@ -6,6 +6,7 @@
 use super::*;
 use crate::executor::Executor;
 use crate::interesting_val::*;
+use crate::provenance::Provenance;
 use fastgen_common::config;
 use rand::{self, distributions::Uniform, thread_rng, Rng, RngCore};
 use std::collections::HashMap;
@@ -180,7 +181,8 @@ pub fn run_afl_mutator(executor: &mut Executor, buf: &mut Vec<u8>) {
 
     for i in 0..max_times {
         let mut buf = buf.clone();
-        havoc_flip(&mut buf, max_stacking, choice_range);
+        havoc_flip(&mut buf, max_stacking, choice_range, executor.cmd.max_len);
+        executor.provenance = Some(Provenance::mutated());
         executor.run_sync(&buf);
     }
 }
@@ -225,6 +227,7 @@ fn splice(executor: &mut Executor, buf: &mut Vec<u8>) -> bool {
     let buf1 = buf.clone();
     if let Some(buf2) = executor.random_input_buf() {
         if let Some(new_buf) = splice_two_vec(&buf1, &buf2) {
+            executor.provenance = Some(Provenance::mutated());
             executor.run_sync(&new_buf);
             true
         } else {
@@ -236,7 +239,12 @@ fn splice(executor: &mut Executor, buf: &mut Vec<u8>) -> bool {
 }
 
 // TODO both endian?
-fn havoc_flip(buf: &mut Vec<u8>, max_stacking: usize, choice_range: Uniform<u32>) {
+fn havoc_flip(
+    buf: &mut Vec<u8>,
+    max_stacking: usize,
+    choice_range: Uniform<u32>,
+    max_len: usize,
+) {
     let mut rng = rand::thread_rng();
     let mut byte_len = buf.len() as u32;
     let use_stacking = 1 + rng.gen_range(0, max_stacking);
@@ -295,7 +303,7 @@ fn havoc_flip(buf: &mut Vec<u8>, max_stacking: usize, choice_range: Uniform<u32>
                 // insert bytes
                 let add_len = rng.gen_range(1, 5);
                 let new_len = byte_len + add_len;
-                if new_len < config::MAX_INPUT_LEN as u32 {
+                if new_len as usize <= max_len {
                     let byte_idx: u32 = rng.gen_range(0, byte_len);
                     byte_len = new_len;
                     for i in 0..add_len {
@@ -310,7 +318,7 @@ fn havoc_flip(buf: &mut Vec<u8>, max_stacking: usize, choice_range: Uniform<u32>
 
 fn random_len(executor: &mut Executor, buf: &mut Vec<u8>) {
     let len = buf.len();
-    if len > config::MAX_INPUT_LEN {
+    if len >= executor.cmd.max_len {
         return;
     }
 
@@ -324,7 +332,8 @@ fn random_len(executor: &mut Executor, buf: &mut Vec<u8>) {
         let mut v = vec![0u8; step];
         rng.fill_bytes(&mut v);
         buf.append(&mut v);
-        if buf.len() < config::MAX_INPUT_LEN {
+        if buf.len() <= executor.cmd.max_len {
+            executor.provenance = Some(Provenance::mutated());
             executor.run_sync(&buf);
         } else {
             break;
@@ -334,7 +343,7 @@ fn random_len(executor: &mut Executor, buf: &mut Vec<u8>) {
 
 fn add_small_len(executor: &mut Executor, buf: &mut Vec<u8>) {
     let len = buf.len();
-    if len > config::MAX_INPUT_LEN {
+    if len >= executor.cmd.max_len {
         return;
     }
 
@@ -346,7 +355,8 @@ fn add_small_len(executor: &mut Executor, buf: &mut Vec<u8>) {
         rng.fill_bytes(&mut v);
         buf.append(&mut v);
         step = step * 2;
-        if buf.len() < config::MAX_INPUT_LEN {
+        if buf.len() <= executor.cmd.max_len {
+            executor.provenance = Some(Provenance::mutated());
             executor.run_sync(&buf);
         } else {
             break;
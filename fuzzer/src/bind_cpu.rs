@@ -0,0 +1,137 @@
+// Pin graders and tracker/solver pairs to their own cores, like AFL.
+// A core is busy if any thread on the box (other fastgen or AFL instances)
+// is already bound to it alone. Threads inherit the binding, so do it before
+// spawning the forkserver, the track child or the solver.
+
+use fastgen_common::defs;
+use nix::{
+    sched::{sched_setaffinity, CpuSet},
+    unistd::Pid,
+};
+use std::{collections::HashSet, env, fs, path::Path};
+
+fn num_cpus() -> usize {
+    let n = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if n > 0 {
+        n as usize
+    } else {
+        1
+    }
+}
+
+// "Cpus_allowed_list:\t3" means the thread is bound to core 3 alone.
+// Kernel threads have no VmSize, the per-core ones (ksoftirqd/N, kworker/N..)
+// do not make their core busy, like in afl-fuzz.
+fn parse_bound_cpu(status: &str) -> Option<usize> {
+    if !status.lines().any(|line| line.starts_with("VmSize:")) {
+        return None;
+    }
+    for line in status.lines() {
+        if line.starts_with("Cpus_allowed_list:") {
+            let list = line["Cpus_allowed_list:".len()..].trim();
+            if list.contains(|c| c == ',' || c == '-') {
+                return None;
+            }
+            return list.parse::<usize>().ok();
+        }
+    }
+    None
+}
+
+fn find_busy_cpus() -> HashSet<usize> {
+    let mut busy = HashSet::new();
+    let proc_dir = match Path::new("/proc").read_dir() {
+        Ok(dir) => dir,
+        Err(_) => return busy,
+    };
+    for entry in proc_dir.flatten() {
+        let is_pid = entry
+            .file_name()
+            .to_str()
+            .map(|name| name.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false);
+        if !is_pid {
+            continue;
+        }
+        // threads are bound one by one, so look at each of them
+        if let Ok(tasks) = entry.path().join("task").read_dir() {
+            for task in tasks.flatten() {
+                if let Ok(status) = fs::read_to_string(task.path().join("status")) {
+                    if let Some(cpu) = parse_bound_cpu(&status) {
+                        busy.insert(cpu);
+                    }
+                }
+            }
+        }
+    }
+    busy
+}
+
+// Up to `ask_num` free cores, or nothing if binding is disabled.
+pub fn find_free_cpus(ask_num: usize) -> Vec<usize> {
+    if env::var(defs::DISABLE_CPU_BINDING_VAR).is_ok() {
+        info!("CPU binding is disabled by {}", defs::DISABLE_CPU_BINDING_VAR);
+        return vec![];
+    }
+    let busy = find_busy_cpus();
+    let free: Vec<usize> = (0..num_cpus()).filter(|c| !busy.contains(c)).collect();
+    if free.len() < ask_num {
+        warn!(
+            "Only {} free cores for {} bound threads, some of them will share",
+            free.len(),
+            ask_num
+        );
+    }
+    info!("Free cores: {:?}", free);
+    free
+}
+
+// The core for the `idx`th bound thread, sharing cores if there are too few.
+pub fn pick_cpu(free_cpus: &Vec<usize>, idx: usize) -> Option<usize> {
+    if free_cpus.is_empty() {
+        None
+    } else {
+        Some(free_cpus[idx % free_cpus.len()])
+    }
+}
+
+pub fn bind_thread_to_cpu_core(cid: usize) {
+    let mut cpu_set = CpuSet::new();
+    if cpu_set.set(cid).is_err() {
+        warn!("Invalid core {}", cid);
+        return;
+    }
+    match sched_setaffinity(Pid::from_raw(0), &cpu_set) {
+        Ok(_) => debug!("Bound thread to core {}", cid),
+        Err(e) => warn!("Could not bind thread to core {}: {:?}", cid, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bound_cpu() {
+        let afl = "Name:\tafl-fuzz\nVmSize:\t  20480 kB\nCpus_allowed_list:\t3\n";
+        assert_eq!(parse_bound_cpu(afl), Some(3));
+        assert_eq!(parse_bound_cpu("VmSize:\t1 kB\nCpus_allowed_list:\t0-7\n"), None);
+        assert_eq!(parse_bound_cpu("VmSize:\t1 kB\nCpus_allowed_list:\t1,5\n"), None);
+        assert_eq!(parse_bound_cpu("Name:\tbash\nVmSize:\t1 kB\n"), None);
+    }
+
+    #[test]
+    fn test_kernel_thread() {
+        // bound to its core, but it leaves the core free
+        let ksoftirqd = "Name:\tksoftirqd/2\nPid:\t21\nCpus_allowed:\t4\nCpus_allowed_list:\t2\n";
+        assert_eq!(parse_bound_cpu(ksoftirqd), None);
+    }
+
+    #[test]
+    fn test_pick_cpu() {
+        let free = vec![2, 5];
+        assert_eq!(pick_cpu(&free, 0), Some(2));
+        assert_eq!(pick_cpu(&free, 3), Some(5));
+        assert_eq!(pick_cpu(&vec![], 0), None);
+    }
+}
@@ -0,0 +1,286 @@
+// What solving and grading did with each branch, keyed like the flip list by
+// (addr, ctx, order, direction). It is written to branch_stat.csv with every
+// checkpoint and read back on resume, and `fastgen report branches` lists the
+// branches that were tried the most but never flipped, and why.
+
+use crate::{checkpoint::BranchKey, source_map::SourceMap};
+use fastgen_common::defs;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, prelude::*, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+static BRANCH_STAT_HEADER: &str =
+    "addr,ctx,order,direction,bid,hits,sat,unsat,timeouts,skipped,solutions,reached,not_reached,flipped";
+
+static REPORT_HEADER: &str = concat!(
+    "              addr                ctx order dir     hits attempts",
+    "  unsat  tmout   skip     sols  reach !reach  reason                location"
+);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SolveOutcome {
+    Sat,
+    Unsat,
+    // the solver gave up, e.g. on --solver_timeout
+    Timeout,
+    // not tried, by the QSYM filter, the hit count or the local count
+    Skipped,
+}
+
+impl From<z3::SatResult> for SolveOutcome {
+    fn from(res: z3::SatResult) -> Self {
+        match res {
+            z3::SatResult::Sat => SolveOutcome::Sat,
+            z3::SatResult::Unsat => SolveOutcome::Unsat,
+            z3::SatResult::Unknown => SolveOutcome::Timeout,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BranchRecord {
+    // the id from the pass, for the condition locations
+    pub bid: u32,
+    pub hits: u32,
+    pub sat: u32,
+    pub unsat: u32,
+    pub timeouts: u32,
+    pub skipped: u32,
+    pub solutions: u32,
+    // graded solutions that reached the branch without flipping it
+    pub reached: u32,
+    pub not_reached: u32,
+    pub flipped: bool,
+}
+
+impl BranchRecord {
+    pub fn attempts(&self) -> u32 {
+        self.sat + self.unsat + self.timeouts
+    }
+
+    // The most frequent reason it is not flipped yet.
+    pub fn failure_reason(&self) -> &'static str {
+        let reasons = [
+            ("unsat", self.unsat),
+            ("timeout", self.timeouts),
+            ("skipped", self.skipped),
+            ("not reached", self.not_reached),
+            ("reached, not flipped", self.reached),
+        ];
+        // max_by_key takes the last of equal ones, ties go to the solver
+        match reasons.iter().rev().filter(|r| r.1 > 0).max_by_key(|r| r.1) {
+            Some(r) => r.0,
+            // sat, but the solutions were duplicates, too long or still queued
+            None => "not graded",
+        }
+    }
+
+    fn to_csv(&self, key: &BranchKey) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            key.0,
+            key.1,
+            key.2,
+            key.3,
+            self.bid,
+            self.hits,
+            self.sat,
+            self.unsat,
+            self.timeouts,
+            self.skipped,
+            self.solutions,
+            self.reached,
+            self.not_reached,
+            self.flipped as u8
+        )
+    }
+
+    fn from_csv(line: &str) -> Option<(BranchKey, Self)> {
+        let fields: Vec<u64> = line
+            .split(',')
+            .map(|f| f.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        if fields.len() != 14 {
+            return None;
+        }
+        let key = (fields[0], fields[1], fields[2] as u32, fields[3]);
+        let rec = Self {
+            bid: fields[4] as u32,
+            hits: fields[5] as u32,
+            sat: fields[6] as u32,
+            unsat: fields[7] as u32,
+            timeouts: fields[8] as u32,
+            skipped: fields[9] as u32,
+            solutions: fields[10] as u32,
+            reached: fields[11] as u32,
+            not_reached: fields[12] as u32,
+            flipped: fields[13] != 0,
+        };
+        Some((key, rec))
+    }
+}
+
+pub struct BranchStats {
+    records: RwLock<HashMap<BranchKey, BranchRecord>>,
+}
+
+impl BranchStats {
+    pub fn new() -> Self {
+        Self {
+            records: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn solved(&self, key: BranchKey, bid: u32, outcome: SolveOutcome, num_solutions: usize) {
+        let mut records = self.records.write().unwrap();
+        let rec = records.entry(key).or_default();
+        rec.bid = bid;
+        rec.hits += 1;
+        match outcome {
+            SolveOutcome::Sat => rec.sat += 1,
+            SolveOutcome::Unsat => rec.unsat += 1,
+            SolveOutcome::Timeout => rec.timeouts += 1,
+            SolveOutcome::Skipped => rec.skipped += 1,
+        }
+        rec.solutions += num_solutions as u32;
+    }
+
+    pub fn graded(&self, key: BranchKey, flipped: usize, reached: usize, not_reached: usize) {
+        let mut records = self.records.write().unwrap();
+        let rec = records.entry(key).or_default();
+        rec.flipped |= flipped > 0;
+        rec.reached += reached as u32;
+        rec.not_reached += not_reached as u32;
+    }
+
+    // Write to a temporary file first, like the checkpoint.
+    pub fn save(&self, out_dir: &Path) {
+        let path = out_dir.join(defs::BRANCH_STAT_FILE);
+        let tmp_path = path.with_extension("tmp");
+        let ret = fs::File::create(&tmp_path).and_then(|f| {
+            let mut w = BufWriter::new(f);
+            writeln!(w, "{}", BRANCH_STAT_HEADER)?;
+            for (key, rec) in self.records.read().unwrap().iter() {
+                writeln!(w, "{}", rec.to_csv(key))?;
+            }
+            w.flush()
+        });
+        if let Err(e) = ret.and_then(|_| fs::rename(&tmp_path, &path)) {
+            warn!("Could not save branch stats {:?}: {:?}", path, e);
+        }
+    }
+
+    // A checkpoint from before the branch stats has no file, start empty then.
+    pub fn load(&self, out_dir: &Path) -> io::Result<()> {
+        match read_records(&out_dir.join(defs::BRANCH_STAT_FILE)) {
+            Ok(records) => {
+                *self.records.write().unwrap() = records;
+                Ok(())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn read_records(path: &Path) -> io::Result<HashMap<BranchKey, BranchRecord>> {
+    let f = fs::File::open(path)?;
+    let mut records = HashMap::new();
+    for line in BufReader::new(f).lines().skip(1) {
+        let line = line?;
+        match BranchRecord::from_csv(&line) {
+            Some((key, rec)) => {
+                records.insert(key, rec);
+            }
+            None => warn!("Skip bad line in {:?}: {}", path, line),
+        }
+    }
+    Ok(records)
+}
+
+// Unflipped branches, the most solve attempts first, then the most hits.
+fn hardest(
+    records: &HashMap<BranchKey, BranchRecord>,
+    num: usize,
+) -> Vec<(&BranchKey, &BranchRecord)> {
+    let mut list: Vec<_> = records.iter().filter(|(_, rec)| !rec.flipped).collect();
+    list.sort_by(|a, b| (b.1.attempts(), b.1.hits, a.0).cmp(&(a.1.attempts(), a.1.hits, b.0)));
+    list.truncate(num);
+    list
+}
+
+// The file is in the output directory, or in its angora/ with --sync_afl.
+pub fn report_branches(out_dir: &str, num: usize, source_map: &SourceMap) {
+    let mut path = PathBuf::from(out_dir).join(defs::BRANCH_STAT_FILE);
+    if !path.is_file() {
+        path = PathBuf::from(out_dir)
+            .join(defs::ANGORA_DIR_NAME)
+            .join(defs::BRANCH_STAT_FILE);
+    }
+    let records = read_records(&path).expect("Could not read the branch stats.");
+    let num_flipped = records.values().filter(|rec| rec.flipped).count();
+    println!(
+        "{} branches, {} flipped, the hardest {} not flipped:",
+        records.len(),
+        num_flipped,
+        std::cmp::min(num, records.len() - num_flipped)
+    );
+    println!("{}", REPORT_HEADER);
+    for (key, rec) in hardest(&records, num) {
+        println!(
+            "{:>#18x} {:>#18x} {:>5} {:>3} {:>8} {:>8} {:>6} {:>6} {:>6} {:>8} {:>6} {:>6}  {:<20}  {}",
+            key.0,
+            key.1,
+            key.2,
+            key.3,
+            rec.hits,
+            rec.attempts(),
+            rec.unsat,
+            rec.timeouts,
+            rec.skipped,
+            rec.solutions,
+            rec.reached,
+            rec.not_reached,
+            rec.failure_reason(),
+            source_map
+                .locate(rec.bid, key.0)
+                .map(|loc| loc.to_string())
+                .unwrap_or_else(|| "?".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_branch_stats() {
+        let stats = BranchStats::new();
+        stats.solved((1, 0, 1, 0), 5, SolveOutcome::Unsat, 0);
+        stats.solved((1, 0, 1, 0), 5, SolveOutcome::Unsat, 0);
+        stats.solved((1, 0, 1, 0), 5, SolveOutcome::Sat, 2);
+        stats.graded((1, 0, 1, 0), 0, 0, 2);
+        stats.solved((2, 0, 1, 1), 6, SolveOutcome::Sat, 1);
+        stats.graded((2, 0, 1, 1), 1, 0, 0);
+        stats.solved((3, 0, 1, 0), 7, SolveOutcome::Skipped, 0);
+
+        let records = stats.records.read().unwrap();
+        let rec = &records[&(1, 0, 1, 0)];
+        assert_eq!((rec.hits, rec.attempts(), rec.solutions), (3, 3, 2));
+        assert_eq!(rec.failure_reason(), "unsat");
+        assert_eq!(records[&(3, 0, 1, 0)].failure_reason(), "skipped");
+
+        let list = hardest(&records, 10);
+        assert_eq!(list.len(), 2);
+        assert_eq!(*list[0].0, (1, 0, 1, 0));
+
+        let line = rec.to_csv(&(1, 0, 1, 0));
+        assert_eq!(BranchRecord::from_csv(&line), Some(((1, 0, 1, 0), rec.clone())));
+        assert_eq!(BranchRecord::from_csv("1,2,3"), None);
+    }
+}
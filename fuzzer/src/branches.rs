@@ -1,16 +1,20 @@
 use crate::status_type::StatusType;
-use fastgen_common::{config::BRANCHES_SIZE, shm::SHM};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use fastgen_common::{defs, shm::SHM};
 #[cfg(feature = "unstable")]
 use std::intrinsics::unlikely;
 use std::{
-    self,
+    self, fs,
+    io::{self, prelude::*},
+    path::Path,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc, RwLock,
     },
 };
 
-pub type BranchBuf = [u8; BRANCHES_SIZE];
+// The map size is chosen at startup, see --map-size.
+pub type BranchBuf = Box<[u8]>;
 #[cfg(target_pointer_width = "32")]
 type BranchEntry = u32;
 #[cfg(target_pointer_width = "64")]
@@ -19,7 +23,6 @@ type BranchEntry = u64;
 const ENTRY_SIZE: usize = 4;
 #[cfg(target_pointer_width = "64")]
 const ENTRY_SIZE: usize = 8;
-type BranchBufPlus = [BranchEntry; BRANCHES_SIZE / ENTRY_SIZE];
 
 // Map of bit bucket
 // [1], [2], [3], [4, 7], [8, 15], [16, 31], [32, 127], [128, infinity]
@@ -38,46 +41,157 @@ static COUNT_LOOKUP: [u8; 256] = [
     128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128,
 ];
 
-macro_rules! cast {
-    ($ptr:expr) => {{
-        unsafe { std::mem::transmute($ptr) }
-    }};
-}
-
 pub struct GlobalBranches {
-    virgin_branches: RwLock<Box<BranchBuf>>,
-    tmouts_branches: RwLock<Box<BranchBuf>>,
-    crashes_branches: RwLock<Box<BranchBuf>>,
+    virgin_branches: RwLock<BranchBuf>,
+    tmouts_branches: RwLock<BranchBuf>,
+    crashes_branches: RwLock<BranchBuf>,
+    ooms_branches: RwLock<BranchBuf>,
+    // edges whose hit counts vary between runs of the same input, 1 if unstable
+    unstable_branches: RwLock<BranchBuf>,
+    size: usize,
     density: AtomicUsize,
+    num_unstable: AtomicUsize,
 }
 
 impl GlobalBranches {
-    pub fn new() -> Self {
+    // `size` is a power of two, a multiple of ENTRY_SIZE.
+    pub fn new(size: usize) -> Self {
         Self {
-            virgin_branches: RwLock::new(Box::new([255u8; BRANCHES_SIZE])),
-            tmouts_branches: RwLock::new(Box::new([255u8; BRANCHES_SIZE])),
-            crashes_branches: RwLock::new(Box::new([255u8; BRANCHES_SIZE])),
+            virgin_branches: RwLock::new(vec![255u8; size].into_boxed_slice()),
+            tmouts_branches: RwLock::new(vec![255u8; size].into_boxed_slice()),
+            crashes_branches: RwLock::new(vec![255u8; size].into_boxed_slice()),
+            ooms_branches: RwLock::new(vec![255u8; size].into_boxed_slice()),
+            unstable_branches: RwLock::new(vec![0u8; size].into_boxed_slice()),
+            size,
             density: AtomicUsize::new(0),
+            num_unstable: AtomicUsize::new(0),
         }
     }
 
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
     pub fn get_density(&self) -> f32 {
         let d = self.density.load(Ordering::Relaxed);
-        (d * 10000 / BRANCHES_SIZE) as f32 / 100.0
+        (d * 10000 / self.size) as f32 / 100.0
+    }
+
+    // edges hit so far
+    pub fn get_num_edges(&self) -> usize {
+        self.density.load(Ordering::Relaxed)
+    }
+
+    pub fn get_num_unstable(&self) -> usize {
+        self.num_unstable.load(Ordering::Relaxed)
+    }
+
+    fn maps(&self) -> [&RwLock<BranchBuf>; 5] {
+        [
+            &self.virgin_branches,
+            &self.tmouts_branches,
+            &self.crashes_branches,
+            &self.ooms_branches,
+            &self.unstable_branches,
+        ]
+    }
+
+    // The bitmaps and counters, see checkpoint.rs.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_u64::<LittleEndian>(self.size as u64)?;
+        for map in self.maps().iter() {
+            w.write_all(&map.read().unwrap()[..])?;
+        }
+        w.write_u64::<LittleEndian>(self.density.load(Ordering::Relaxed) as u64)?;
+        w.write_u64::<LittleEndian>(self.get_num_unstable() as u64)?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(&self, r: &mut R) -> io::Result<()> {
+        if r.read_u64::<LittleEndian>()? != self.size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the map size has changed",
+            ));
+        }
+        for map in self.maps().iter() {
+            r.read_exact(&mut map.write().unwrap()[..])?;
+        }
+        let density = r.read_u64::<LittleEndian>()? as usize;
+        self.density.store(density, Ordering::Relaxed);
+        let num_unstable = r.read_u64::<LittleEndian>()? as usize;
+        self.num_unstable.store(num_unstable, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Percentage of the covered edges that are stable, like AFL's stability.
+    pub fn get_stability(&self) -> f32 {
+        let d = self.density.load(Ordering::Relaxed);
+        let u = self.get_num_unstable();
+        if d == 0 || u >= d {
+            return if u == 0 { 100.0 } else { 0.0 };
+        }
+        ((d - u) * 10000 / d) as f32 / 100.0
     }
 }
 
+// Recorded in the output directory like the coverage mode, for resuming and cmin.
+pub fn load_map_size(out_dir: &Path) -> io::Result<Option<usize>> {
+    match fs::read_to_string(out_dir.join(defs::MAP_SIZE_FILE)) {
+        Ok(s) => s
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn save_map_size(out_dir: &Path, size: usize) -> io::Result<()> {
+    fs::write(out_dir.join(defs::MAP_SIZE_FILE), format!("{}\n", size))
+}
+
+// Edges that are hit in only one of the paths, or in different buckets.
+// Both paths are sorted by index.
+fn diff_paths(a: &Vec<(usize, u8)>, b: &Vec<(usize, u8)>) -> Vec<usize> {
+    let mut diff = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].0 == b[j].0 {
+            if a[i].1 != b[j].1 {
+                diff.push(a[i].0);
+            }
+            i += 1;
+            j += 1;
+        } else if a[i].0 < b[j].0 {
+            diff.push(a[i].0);
+            i += 1;
+        } else {
+            diff.push(b[j].0);
+            j += 1;
+        }
+    }
+    diff.extend(a[i..].iter().map(|br| br.0));
+    diff.extend(b[j..].iter().map(|br| br.0));
+    diff
+}
+
 pub struct Branches {
     global: Arc<GlobalBranches>,
-    trace: SHM<BranchBuf>,
+    trace: SHM<u8>,
 }
 
 impl Branches {
     pub fn new(global: Arc<GlobalBranches>) -> Self {
-        let trace = SHM::<BranchBuf>::new();
+        let trace = SHM::<u8>::new_bytes(global.get_size());
         Self { global, trace }
     }
 
+    pub fn get_size(&self) -> usize {
+        self.global.get_size()
+    }
+
     pub fn clear_trace(&mut self) {
         self.trace.clear();
     }
@@ -86,10 +200,13 @@ impl Branches {
         self.trace.get_id()
     }
 
-    fn get_path(&self) -> Vec<(usize, u8)> {
+    pub fn get_path(&self) -> Vec<(usize, u8)> {
         let mut path = Vec::<(usize, u8)>::new();
-        let buf_plus: &BranchBufPlus = cast!(&*self.trace);
-        let buf: &BranchBuf = &*self.trace;
+        let buf = self.trace.as_slice();
+        // scan a word at a time, most of the map is zero
+        let buf_plus: &[BranchEntry] = unsafe {
+            std::slice::from_raw_parts(buf.as_ptr() as *const BranchEntry, buf.len() / ENTRY_SIZE)
+        };
         for (i, &v) in buf_plus.iter().enumerate() {
             macro_rules! run_loop {
                 () => {{
@@ -120,35 +237,74 @@ impl Branches {
         path
     }
 
-    pub fn has_new(&mut self, status: StatusType) -> bool {
-        let gb_map = match status {
-            StatusType::Normal => &self.global.virgin_branches,
-            StatusType::Timeout => &self.global.tmouts_branches,
-            StatusType::Crash => &self.global.crashes_branches,
-            _ => {
-                return false;
-            }
-        };
-        let path = self.get_path();
-        //let edge_num = path.len();
+    fn get_map(&self, status: StatusType) -> Option<&RwLock<BranchBuf>> {
+        match status {
+            StatusType::Normal => Some(&self.global.virgin_branches),
+            StatusType::Timeout => Some(&self.global.tmouts_branches),
+            StatusType::Crash => Some(&self.global.crashes_branches),
+            StatusType::Oom => Some(&self.global.ooms_branches),
+            _ => None,
+        }
+    }
 
+    // The bits of `path` that are not in the global map yet, and how many of
+    // them are edges never seen before. Unstable edges are ignored.
+    fn new_bits(
+        &self,
+        gb_map: &RwLock<BranchBuf>,
+        path: &Vec<(usize, u8)>,
+    ) -> (Vec<(usize, u8)>, usize) {
         let mut to_write = vec![];
         let mut num_new_edge = 0;
-        {
-            // read only
-            let gb_map_read = gb_map.read().unwrap();
-            for &br in &path {
-                let gb_v = gb_map_read[br.0];
+        // read only
+        let gb_map_read = gb_map.read().unwrap();
+        let unstable = self.global.unstable_branches.read().unwrap();
+        for &br in path {
+            if unstable[br.0] > 0 {
+                continue;
+            }
+            let gb_v = gb_map_read[br.0];
 
-                if gb_v == 255u8 {
-                    num_new_edge += 1;
-                }
+            if gb_v == 255u8 {
+                num_new_edge += 1;
+            }
 
-                if (br.1 & gb_v) > 0 {
-                    to_write.push((br.0, gb_v & (!br.1)));
-                }
+            if (br.1 & gb_v) > 0 {
+                to_write.push((br.0, gb_v & (!br.1)));
             }
         }
+        (to_write, num_new_edge)
+    }
+
+    // Like has_new, but leaves the global map alone.
+    pub fn is_new(&self, status: StatusType, path: &Vec<(usize, u8)>) -> bool {
+        match self.get_map(status) {
+            Some(gb_map) => !self.new_bits(gb_map, path).0.is_empty(),
+            None => false,
+        }
+    }
+
+    pub fn has_new(&mut self, status: StatusType) -> bool {
+        let path = self.get_path();
+        self.has_new_in(status, &path)
+    }
+
+    pub fn has_new_in(&mut self, status: StatusType, path: &Vec<(usize, u8)>) -> bool {
+        self.count_new_in(status, path).is_some()
+    }
+
+    // Like has_new_in, and returns how many edges were never seen before, 0 if
+    // only a hit count is new. None if nothing is new.
+    pub fn count_new_in(&mut self, status: StatusType, path: &Vec<(usize, u8)>) -> Option<usize> {
+        let gb_map = match self.get_map(status) {
+            Some(gb_map) => gb_map,
+            None => {
+                return None;
+            }
+        };
+        //let edge_num = path.len();
+
+        let (to_write, num_new_edge) = self.new_bits(gb_map, path);
 
         if num_new_edge > 0 {
             if status == StatusType::Normal {
@@ -160,7 +316,7 @@ impl Branches {
         }
 
         if to_write.is_empty() {
-            return false;
+            return None;
         }
 
         {
@@ -171,7 +327,35 @@ impl Branches {
             }
         }
 
-        true
+        Some(num_new_edge)
+    }
+
+    pub fn get_num_edges(&self) -> usize {
+        self.global.get_num_edges()
+    }
+
+    // Compare the current trace with `first`, a path of an earlier run of the
+    // same input, and mark the edges that differ as unstable.
+    // Returns the number of newly marked edges.
+    pub fn mark_unstable(&self, first: &Vec<(usize, u8)>) -> usize {
+        let diff = diff_paths(first, &self.get_path());
+        if diff.is_empty() {
+            return 0;
+        }
+        let mut num_new = 0;
+        {
+            let mut unstable = self.global.unstable_branches.write().unwrap();
+            for idx in diff {
+                if unstable[idx] == 0 {
+                    unstable[idx] = 1;
+                    num_new += 1;
+                }
+            }
+        }
+        self.global
+            .num_unstable
+            .fetch_add(num_new, Ordering::Relaxed);
+        num_new
     }
 }
 
@@ -181,6 +365,53 @@ impl std::fmt::Debug for Branches {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_paths() {
+        let a = vec![(1, 1), (4, 2), (8, 4)];
+        let b = vec![(1, 1), (4, 4), (9, 1)];
+        assert_eq!(diff_paths(&a, &b), vec![4, 8, 9]);
+        assert!(diff_paths(&a, &a).is_empty());
+        assert_eq!(diff_paths(&a, &vec![]), vec![1, 4, 8]);
+    }
+
+    #[test]
+    fn test_map_size_file() {
+        let dir = std::env::temp_dir().join(format!("fastgen_map_size_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(load_map_size(&dir).unwrap(), None);
+        save_map_size(&dir, 1 << 18).unwrap();
+        assert_eq!(load_map_size(&dir).unwrap(), Some(1 << 18));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_small_map() {
+        let global = Arc::new(GlobalBranches::new(1 << 16));
+        let mut br = Branches::new(global.clone());
+        br.clear_trace();
+        assert!(br.get_path().is_empty());
+        {
+            let trace = br.trace.as_mut_slice();
+            trace[4] = 1;
+            trace[65535] = 3;
+        }
+        let path = br.get_path();
+        assert_eq!(path, vec![(4, 1), (65535, COUNT_LOOKUP[3])]);
+        assert_eq!(br.count_new_in(StatusType::Normal, &path), Some(2));
+        assert_eq!(br.count_new_in(StatusType::Normal, &path), None);
+        assert_eq!(global.get_num_edges(), 2);
+
+        let mut buf = vec![];
+        global.write_to(&mut buf).unwrap();
+        assert!(GlobalBranches::new(1 << 20).read_from(&mut &buf[..]).is_err());
+        assert!(GlobalBranches::new(1 << 16).read_from(&mut &buf[..]).is_ok());
+    }
+}
+
 /*
 #[cfg(test)]
 mod tests {
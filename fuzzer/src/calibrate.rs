@@ -0,0 +1,186 @@
+// Calibrate the execution timeouts from the seeds, like AFL does.
+// Each seed is run a few times through the forkserver, and a few of them
+// are tracked once. The timeouts are a multiple of the observed p95.
+// Tracking only drains the pipe here, while fuzzing the child blocks on it
+// until solving is done, so the track timeout also has room for the solver.
+// The edges that vary between the runs of a seed are marked as unstable.
+
+use crate::{
+    branches::GlobalBranches, command::CommandOpt, depot::Depot, executor::Executor,
+    file::read_from_file, status_type::StatusType,
+};
+use fastgen_common::config;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time,
+};
+
+pub fn percentile(samples: &mut Vec<u64>, p: usize) -> u64 {
+    if samples.is_empty() {
+        return 0;
+    }
+    samples.sort_unstable();
+    let idx = (samples.len() * p + 99) / 100;
+    samples[std::cmp::min(idx.saturating_sub(1), samples.len() - 1)]
+}
+
+// p95 (us) -> timeout (ms)
+fn scale_timeout(p95_us: u64, mult: u64, min: u64, max: u64) -> u64 {
+    let tmout = (p95_us * mult + 999) / 1000;
+    std::cmp::min(std::cmp::max(tmout, min), max)
+}
+
+// The solver runs inline with the tracked child, for up to MAX_SOLVE_TIME.
+fn track_timeout(p95_us: u64, max: u64) -> u64 {
+    let run = scale_timeout(p95_us, config::TMOUT_TRACK_MULT, config::TIME_LIMIT_TRACK_MIN, max);
+    let solve = config::MAX_SOLVE_TIME * 1000 + config::TRACK_SOLVE_GRACE;
+    std::cmp::min(run + solve, max)
+}
+
+fn track_once(executor: &mut Executor, buf: &Vec<u8>) -> Option<u64> {
+    let t_start = time::Instant::now();
+    let exit = executor.track_drain(0, buf);
+    if exit.timed_out {
+        None
+    } else {
+        Some(t_start.elapsed().as_micros() as u64)
+    }
+}
+
+// Return the (grading, tracking) timeouts in ms.
+// `cmd` carries the upper bounds, which are also used while calibrating.
+pub fn calibrate_timeouts(
+    cmd: &CommandOpt,
+    global_branches: Arc<GlobalBranches>,
+    depot: Arc<Depot>,
+    forklock: Arc<Mutex<u32>>,
+    seeds_dir: &Path,
+) -> (u64, u64) {
+    let shmid = unsafe {
+        libc::shmget(
+            libc::IPC_PRIVATE,
+            0xc00000000,
+            0o644 | libc::IPC_CREAT | libc::SHM_NORESERVE,
+        )
+    };
+
+    let mut executor = Executor::new(
+        cmd.specify(1),
+        global_branches.clone(),
+        depot,
+        shmid,
+        true,
+        forklock,
+    );
+
+    let mut samples = vec![];
+    let mut track_samples = vec![];
+    let mut num_tmouts = 0;
+    if let Ok(entries) = seeds_dir.read_dir() {
+        for entry in entries {
+            if let Ok(entry) = entry {
+                let path = entry.path();
+                if !path.is_file() {
+                    continue;
+                }
+                if let Some(buf) = read_from_file(&path) {
+                    if buf.len() > cmd.max_len {
+                        continue;
+                    }
+                    let mut first_path = None;
+                    let mut runs = config::CALIBRATE_RUNS;
+                    let mut i = 0;
+                    while i < runs {
+                        let (status, used_t) = executor.run_calibrate(&buf);
+                        if status == StatusType::Timeout || status == StatusType::Skip {
+                            num_tmouts += 1;
+                            break;
+                        }
+                        samples.push(used_t.as_micros() as u64);
+                        match first_path {
+                            None => first_path = Some(executor.branches.get_path()),
+                            Some(ref path) => {
+                                if executor.branches.mark_unstable(path) > 0 {
+                                    runs = config::MAX_INVARIABLE_NUM;
+                                }
+                            }
+                        }
+                        i += 1;
+                    }
+                    if track_samples.len() < config::CALIBRATE_TRACK_SEEDS {
+                        match track_once(&mut executor, &buf) {
+                            Some(used_us) => track_samples.push(used_us),
+                            None => warn!("Seed {:?} timed out while tracking", path),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe { libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut()) };
+
+    if num_tmouts > 0 {
+        warn!(
+            "{} seeds timed out with the {}ms limit while calibrating",
+            num_tmouts, cmd.time_limit
+        );
+    }
+
+    let time_limit = if samples.is_empty() {
+        cmd.time_limit
+    } else {
+        scale_timeout(
+            percentile(&mut samples, config::CALIBRATE_PERCENTILE),
+            config::TMOUT_MULT,
+            config::TIME_LIMIT_MIN,
+            cmd.time_limit,
+        )
+    };
+    let track_time_limit = if track_samples.is_empty() {
+        cmd.track_time_limit
+    } else {
+        track_timeout(
+            percentile(&mut track_samples, config::CALIBRATE_PERCENTILE),
+            cmd.track_time_limit,
+        )
+    };
+    info!(
+        "Calibrated from {} runs: time limit {}ms, track time limit {}ms, {} unstable edges",
+        samples.len(),
+        time_limit,
+        track_time_limit,
+        global_branches.get_num_unstable()
+    );
+    (time_limit, track_time_limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let mut samples: Vec<u64> = (1..=100).rev().collect();
+        assert_eq!(percentile(&mut samples, 95), 95);
+        assert_eq!(percentile(&mut samples, 100), 100);
+        assert_eq!(percentile(&mut vec![7], 95), 7);
+        assert_eq!(percentile(&mut vec![], 95), 0);
+    }
+
+    #[test]
+    fn test_scale_timeout() {
+        assert_eq!(scale_timeout(1500, 5, 20, 1000), 20);
+        assert_eq!(scale_timeout(30000, 5, 20, 1000), 150);
+        assert_eq!(scale_timeout(500000, 5, 20, 1000), 1000);
+    }
+
+    #[test]
+    fn test_track_timeout() {
+        // a fast seed still leaves the solver its time
+        assert_eq!(track_timeout(1000, 120000), 101000);
+        assert_eq!(track_timeout(500000, 120000), 105000);
+        assert_eq!(track_timeout(5000000, 120000), 120000);
+    }
+}
@@ -0,0 +1,162 @@
+// Optional cgroup v2 backend for resource limits.
+// RLIMIT_AS is meaningless for ASan builds and counts the huge SHM_NORESERVE
+// union table, so instead each executor puts its children into its own cgroup
+// with memory.max and pids.max, which also lets us tell OOM kills apart.
+
+use std::{
+    ffi::CString,
+    fs, io,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+    thread, time,
+};
+
+static CONTROLLERS: &str = "+memory +pids";
+// the leaf of the fuzzer itself, if it had to leave the delegated root
+static MAIN_CGROUP: &str = "main";
+
+fn write_file(path: &Path, content: &str) -> io::Result<()> {
+    fs::write(path, content.as_bytes())
+}
+
+// Let the children of `dir` use the memory and pids controllers.
+pub fn enable_controllers(dir: &Path) -> io::Result<()> {
+    write_file(&dir.join("cgroup.subtree_control"), CONTROLLERS)
+}
+
+// Create the per-process cgroup below the delegated `root` that holds the
+// per-executor ones.
+// A cgroup with processes can not enable controllers for its children (EBUSY),
+// e.g. the scope of `systemd-run --scope -p Delegate=yes` that we run in. Then
+// the fuzzer moves itself to the leaf <dir>/main first.
+pub fn create_root(root: &Path) -> PathBuf {
+    let pid = unsafe { libc::getpid() as usize };
+    let dir = root.join(format!("fastgen_{}", pid));
+    fs::create_dir(&dir).expect("Could not create cgroup, is it delegated?");
+    if let Err(e) = enable_controllers(root) {
+        if e.raw_os_error() != Some(libc::EBUSY) {
+            panic!("Could not enable cgroup controllers, is it delegated? {:?}", e);
+        }
+        let main = dir.join(MAIN_CGROUP);
+        fs::create_dir(&main).expect("Could not create cgroup.");
+        write_file(&main.join("cgroup.procs"), &pid.to_string())
+            .expect("Could not move the fuzzer out of the cgroup root, which has to be empty.");
+        info!("Moved the fuzzer to cgroup {:?}", main);
+        enable_controllers(root)
+            .expect("Could not enable cgroup controllers, are other processes in the root?");
+    }
+    enable_controllers(&dir).expect("Could not enable cgroup controllers.");
+    dir
+}
+
+pub fn remove_root(dir: &Path) {
+    // we are still in there, the cgroup goes with the delegated one
+    if dir.join(MAIN_CGROUP).is_dir() {
+        return;
+    }
+    if let Err(e) = fs::remove_dir(dir) {
+        warn!("Could not remove cgroup {:?}: {:?}", dir, e);
+    }
+}
+
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+    procs: CString,
+    oom_kills: u64,
+}
+
+impl Cgroup {
+    // mem_limit in MB, 0 for unlimited
+    pub fn new(path: PathBuf, mem_limit: u64, pids_max: u64) -> Option<Self> {
+        if let Err(e) = fs::create_dir(&path) {
+            if e.kind() != io::ErrorKind::AlreadyExists {
+                warn!("Could not create cgroup {:?}: {:?}", path, e);
+                return None;
+            }
+        }
+        let mem = if mem_limit == 0 {
+            "max".to_string()
+        } else {
+            (mem_limit << 20).to_string()
+        };
+        let ret = write_file(&path.join("memory.max"), &mem)
+            .and_then(|_| write_file(&path.join("pids.max"), &pids_max.to_string()));
+        if let Err(e) = ret {
+            warn!("Could not set limits of cgroup {:?}: {:?}", path, e);
+            return None;
+        }
+        // missing without swap accounting
+        let _ = write_file(&path.join("memory.swap.max"), "0");
+        let procs = CString::new(path.join("cgroup.procs").as_os_str().as_bytes()).unwrap();
+        let mut cg = Self {
+            path,
+            procs,
+            oom_kills: 0,
+        };
+        cg.oom_kills = cg.read_oom_kills();
+        Some(cg)
+    }
+
+    fn read_oom_kills(&self) -> u64 {
+        let events = fs::read_to_string(self.path.join("memory.events")).unwrap_or_default();
+        for line in events.lines() {
+            let mut it = line.split_whitespace();
+            if it.next() == Some("oom_kill") {
+                return it.next().and_then(|v| v.parse().ok()).unwrap_or(0);
+            }
+        }
+        0
+    }
+
+    // Whether the kernel OOM-killed something in here since the last call.
+    pub fn check_oom(&mut self) -> bool {
+        let oom_kills = self.read_oom_kills();
+        let ret = oom_kills > self.oom_kills;
+        self.oom_kills = oom_kills;
+        ret
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // cgroup.kill needs linux 5.14, the retries cover older kernels
+        let _ = write_file(&self.path.join("cgroup.kill"), "1");
+        for _ in 0..10 {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        warn!("Could not remove cgroup {:?}", self.path);
+    }
+}
+
+pub trait ConfigCgroup {
+    fn join_cgroup(&mut self, cgroup: Option<&Cgroup>) -> &mut Self;
+}
+
+impl ConfigCgroup for Command {
+    fn join_cgroup(&mut self, cgroup: Option<&Cgroup>) -> &mut Self {
+        if let Some(cg) = cgroup {
+            let procs = cg.procs.clone();
+            let func = move || {
+                // "0" moves the writing process itself
+                let fd = unsafe { libc::open(procs.as_ptr(), libc::O_WRONLY) };
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let ret = unsafe { libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1) };
+                unsafe { libc::close(fd) };
+                if ret < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            };
+            unsafe { self.pre_exec(func) }
+        } else {
+            self
+        }
+    }
+}
@@ -0,0 +1,261 @@
+// Campaign state that is written to the output directory every few minutes
+// and when fuzzing ends, so that `-i -` resumes exactly where it stopped:
+// the coverage bitmaps, the branches that were flipped or tried, the QSYM
+// filter, the depot counters and the queue, the timeouts, and the run time of
+// the campaign. The branch stats go to their own CSV next to it, `fastgen
+// report branches` reads it.
+
+use crate::{
+    branch_stats::BranchStats, branches::GlobalBranches, command::CommandOpt, cpp_interface::*,
+    depot::Depot, timeline::Timeline,
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use fastgen_common::defs;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, prelude::*, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+static MAGIC: &[u8; 4] = b"FGCK";
+const VERSION: u32 = 2;
+
+pub type BranchKey = (u64, u64, u32, u64);
+
+pub struct CampaignState {
+    pub global_branches: Arc<GlobalBranches>,
+    pub depot: Arc<Depot>,
+    pub branch_gencount: Arc<RwLock<HashMap<BranchKey, u32>>>,
+    pub branch_fliplist: Arc<RwLock<HashSet<BranchKey>>>,
+    // one for each tracker thread, the hitcount <= 5 gate counts per tracker
+    pub branch_hitcounts: Vec<Arc<RwLock<HashMap<BranchKey, u32>>>>,
+    pub branch_stats: Arc<BranchStats>,
+    pub timeline: Arc<Timeline>,
+}
+
+pub fn checkpoint_path(out_dir: &Path) -> PathBuf {
+    out_dir.join(defs::CHECKPOINT_FILE)
+}
+
+fn write_key<W: Write>(w: &mut W, key: &BranchKey) -> io::Result<()> {
+    w.write_u64::<LittleEndian>(key.0)?;
+    w.write_u64::<LittleEndian>(key.1)?;
+    w.write_u32::<LittleEndian>(key.2)?;
+    w.write_u64::<LittleEndian>(key.3)
+}
+
+fn read_key<R: Read>(r: &mut R) -> io::Result<BranchKey> {
+    Ok((
+        r.read_u64::<LittleEndian>()?,
+        r.read_u64::<LittleEndian>()?,
+        r.read_u32::<LittleEndian>()?,
+        r.read_u64::<LittleEndian>()?,
+    ))
+}
+
+fn write_counts<W: Write>(w: &mut W, map: &HashMap<BranchKey, u32>) -> io::Result<()> {
+    w.write_u64::<LittleEndian>(map.len() as u64)?;
+    for (key, count) in map {
+        write_key(w, key)?;
+        w.write_u32::<LittleEndian>(*count)?;
+    }
+    Ok(())
+}
+
+fn read_counts<R: Read>(r: &mut R, map: &mut HashMap<BranchKey, u32>) -> io::Result<()> {
+    let len = r.read_u64::<LittleEndian>()?;
+    for _ in 0..len {
+        let key = read_key(r)?;
+        map.insert(key, r.read_u32::<LittleEndian>()?);
+    }
+    Ok(())
+}
+
+fn write_hitcounts<W: Write>(
+    w: &mut W,
+    maps: &[Arc<RwLock<HashMap<BranchKey, u32>>>],
+) -> io::Result<()> {
+    w.write_u64::<LittleEndian>(maps.len() as u64)?;
+    for map in maps {
+        write_counts(w, &map.read().unwrap())?;
+    }
+    Ok(())
+}
+
+// Resumed with another number of trackers, each of them gets the highest
+// counts of all of them.
+fn read_hitcounts<R: Read>(
+    r: &mut R,
+    maps: &[Arc<RwLock<HashMap<BranchKey, u32>>>],
+) -> io::Result<()> {
+    let num = r.read_u64::<LittleEndian>()? as usize;
+    let mut saved = vec![HashMap::new(); num];
+    for map in saved.iter_mut() {
+        read_counts(r, map)?;
+    }
+    if num == maps.len() {
+        for (map, s) in maps.iter().zip(saved) {
+            *map.write().unwrap() = s;
+        }
+        return Ok(());
+    }
+    let mut union = HashMap::new();
+    for s in saved {
+        for (key, count) in s {
+            let c = union.entry(key).or_insert(0);
+            *c = std::cmp::max(*c, count);
+        }
+    }
+    for map in maps {
+        *map.write().unwrap() = union.clone();
+    }
+    Ok(())
+}
+
+fn write_set<W: Write>(w: &mut W, set: &HashSet<BranchKey>) -> io::Result<()> {
+    w.write_u64::<LittleEndian>(set.len() as u64)?;
+    for key in set {
+        write_key(w, key)?;
+    }
+    Ok(())
+}
+
+fn read_set<R: Read>(r: &mut R, set: &mut HashSet<BranchKey>) -> io::Result<()> {
+    let len = r.read_u64::<LittleEndian>()?;
+    for _ in 0..len {
+        set.insert(read_key(r)?);
+    }
+    Ok(())
+}
+
+fn write_filter<W: Write>(w: &mut W) -> io::Result<()> {
+    let size = unsafe { filter_map_size() } as usize;
+    let mut trace = vec![0u8; size];
+    let mut context = vec![0u8; size];
+    unsafe { get_filter_state(trace.as_mut_ptr(), context.as_mut_ptr()) };
+    w.write_u64::<LittleEndian>(size as u64)?;
+    w.write_all(&trace)?;
+    w.write_all(&context)
+}
+
+fn read_filter<R: Read>(r: &mut R) -> io::Result<()> {
+    let size = unsafe { filter_map_size() } as usize;
+    if r.read_u64::<LittleEndian>()? != size as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the filter map size has changed",
+        ));
+    }
+    let mut trace = vec![0u8; size];
+    let mut context = vec![0u8; size];
+    r.read_exact(&mut trace)?;
+    r.read_exact(&mut context)?;
+    unsafe { set_filter_state(trace.as_ptr(), context.as_ptr()) };
+    Ok(())
+}
+
+impl CampaignState {
+    fn write_to<W: Write>(&self, w: &mut W, cmd: &CommandOpt) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u32::<LittleEndian>(VERSION)?;
+        w.write_u64::<LittleEndian>(cmd.time_limit)?;
+        w.write_u64::<LittleEndian>(cmd.track_time_limit)?;
+        w.write_u64::<LittleEndian>(self.timeline.run_time_ms())?;
+        self.global_branches.write_to(w)?;
+        self.depot.write_to(w)?;
+        write_counts(w, &self.branch_gencount.read().unwrap())?;
+        write_set(w, &self.branch_fliplist.read().unwrap())?;
+        write_hitcounts(w, &self.branch_hitcounts)?;
+        write_filter(w)
+    }
+
+    // Write to a temporary file first, a crash must not leave half of it.
+    pub fn save(&self, out_dir: &Path, cmd: &CommandOpt) {
+        let path = checkpoint_path(out_dir);
+        let tmp_path = path.with_extension("tmp");
+        let ret = fs::File::create(&tmp_path).and_then(|f| {
+            let mut w = BufWriter::new(f);
+            self.write_to(&mut w, cmd)?;
+            w.flush()
+        });
+        match ret.and_then(|_| fs::rename(&tmp_path, &path)) {
+            Ok(_) => debug!("Saved checkpoint {:?}", path),
+            Err(e) => warn!("Could not save checkpoint {:?}: {:?}", path, e),
+        }
+        self.branch_stats.save(out_dir);
+    }
+
+    // Restore everything and return the (grading, tracking) timeouts in ms.
+    // init_core has to be called before, it clears the QSYM filter.
+    pub fn load(&self, out_dir: &Path) -> io::Result<(u64, u64)> {
+        let f = fs::File::open(checkpoint_path(out_dir))?;
+        let mut r = BufReader::new(f);
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC || r.read_u32::<LittleEndian>()? != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a checkpoint of this version",
+            ));
+        }
+        let time_limit = r.read_u64::<LittleEndian>()?;
+        let track_time_limit = r.read_u64::<LittleEndian>()?;
+        self.timeline.resume_at(r.read_u64::<LittleEndian>()?);
+        self.global_branches.read_from(&mut r)?;
+        self.depot.read_from(&mut r)?;
+        read_counts(&mut r, &mut self.branch_gencount.write().unwrap())?;
+        read_set(&mut r, &mut self.branch_fliplist.write().unwrap())?;
+        read_hitcounts(&mut r, &self.branch_hitcounts)?;
+        read_filter(&mut r)?;
+        self.branch_stats.load(out_dir)?;
+        Ok((time_limit, track_time_limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_roundtrip() {
+        let mut map = HashMap::new();
+        map.insert((1, 2, 3, 4), 5);
+        map.insert((u64::MAX, 0, u32::MAX, 1), 1);
+        let mut set = HashSet::new();
+        set.insert((7, 8, 9, 0));
+
+        let mut buf = vec![];
+        write_counts(&mut buf, &map).unwrap();
+        write_set(&mut buf, &set).unwrap();
+
+        let mut r = &buf[..];
+        let mut map2 = HashMap::new();
+        let mut set2 = HashSet::new();
+        read_counts(&mut r, &mut map2).unwrap();
+        read_set(&mut r, &mut set2).unwrap();
+        assert_eq!(map, map2);
+        assert_eq!(set, set2);
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn test_hitcounts() {
+        let maps: Vec<_> = (0..2).map(|_| Arc::new(RwLock::new(HashMap::new()))).collect();
+        maps[0].write().unwrap().insert((1, 2, 3, 4), 5);
+        maps[1].write().unwrap().insert((1, 2, 3, 4), 2);
+        maps[1].write().unwrap().insert((5, 6, 7, 8), 1);
+        let mut buf = vec![];
+        write_hitcounts(&mut buf, &maps).unwrap();
+
+        let same: Vec<_> = (0..2).map(|_| Arc::new(RwLock::new(HashMap::new()))).collect();
+        read_hitcounts(&mut &buf[..], &same).unwrap();
+        assert_eq!(*same[1].read().unwrap(), *maps[1].read().unwrap());
+
+        let fewer = vec![Arc::new(RwLock::new(HashMap::new()))];
+        read_hitcounts(&mut &buf[..], &fewer).unwrap();
+        let merged = fewer[0].read().unwrap();
+        assert_eq!((merged[&(1, 2, 3, 4)], merged[&(5, 6, 7, 8)]), (5, 1));
+    }
+}
@@ -0,0 +1,213 @@
+// `fastgen cmin`: shrink a corpus to the smallest inputs that keep its
+// coverage, like afl-cmin. Every input is run once through the forkserver,
+// and for each (edge, hit count bucket) tuple the smallest input hitting it
+// is kept.
+
+use crate::{
+    branches::{self, GlobalBranches},
+    command::CommandOpt,
+    depot::Depot,
+    executor::Executor,
+    file::read_from_file,
+    scheduler::SchedulePolicy,
+    status_type::StatusType,
+};
+use fastgen_common::{config, coverage_mode::CoverageMode};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+// The hit count bucket fits in the low byte, the map is at most 2^24.
+fn to_tuples(path: &Vec<(usize, u8)>) -> Vec<u32> {
+    path.iter()
+        .map(|&(idx, bucket)| ((idx as u32) << 8) | bucket as u32)
+        .collect()
+}
+
+// `traces` are the sizes and tuples of the inputs, returns the indices of the
+// inputs to keep.
+pub fn minimize(traces: &Vec<(usize, Vec<u32>)>) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..traces.len()).collect();
+    order.sort_by_key(|&i| (traces[i].0, i));
+
+    // the smallest input for each tuple
+    let mut best = HashMap::<u32, usize>::new();
+    for &i in order.iter().rev() {
+        for &t in &traces[i].1 {
+            best.insert(t, i);
+        }
+    }
+
+    let mut covered = HashSet::<u32>::new();
+    let mut chosen = vec![];
+    for &i in &order {
+        let needed = traces[i]
+            .1
+            .iter()
+            .any(|t| !covered.contains(t) && best[t] == i);
+        if needed {
+            covered.extend(traces[i].1.iter());
+            chosen.push(i);
+        }
+    }
+    chosen
+}
+
+pub fn list_inputs(in_dir: &Path) -> Vec<PathBuf> {
+    let mut inputs: Vec<PathBuf> = in_dir
+        .read_dir()
+        .expect("Could not read input directory.")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    inputs.sort();
+    inputs
+}
+
+// The mode of the campaign whose queue is minimized, if there is one.
+fn campaign_coverage_mode(in_dir: &Path) -> Option<CoverageMode> {
+    let campaign_dir = in_dir.parent()?;
+    match CoverageMode::load(campaign_dir) {
+        Ok(mode) => mode,
+        Err(e) => {
+            warn!("Could not read the coverage mode in {:?}: {:?}", campaign_dir, e);
+            None
+        }
+    }
+}
+
+fn campaign_map_size(in_dir: &Path) -> Option<usize> {
+    let campaign_dir = in_dir.parent()?;
+    match branches::load_map_size(campaign_dir) {
+        Ok(size) => size,
+        Err(e) => {
+            warn!("Could not read the map size in {:?}: {:?}", campaign_dir, e);
+            None
+        }
+    }
+}
+
+pub fn cmin_main(
+    in_dir: &str,
+    out_dir: &str,
+    pargs: Vec<String>,
+    mem_limit: u64,
+    time_limit: u64,
+    coverage_mode: Option<CoverageMode>,
+    map_size: Option<usize>,
+    max_len: usize,
+) {
+    pretty_env_logger::init();
+
+    let out_dir = Path::new(out_dir);
+    fs::create_dir(out_dir).expect("Output directory has existed!");
+    // the forkserver files and the depot of the executor go here
+    let pid = unsafe { libc::getpid() as usize };
+    let work_dir = env::temp_dir().join(format!("fastgen_cmin_{}", pid));
+    fs::create_dir(&work_dir).expect("Could not create work directory.");
+
+    // nothing is tracked, the fast binary stands in for the track one
+    let track_target = pargs[0].clone();
+    let mut cmd = CommandOpt::new(
+        &track_target,
+        pargs,
+        &work_dir,
+        mem_limit,
+        time_limit,
+        config::TIME_LIMIT_TRACK,
+        None,
+        max_len,
+    );
+    cmd.coverage_mode = coverage_mode
+        .or_else(|| campaign_coverage_mode(Path::new(in_dir)))
+        .unwrap_or_default();
+    info!("Coverage mode: {}", cmd.coverage_mode);
+    let map_size = map_size
+        .or_else(|| campaign_map_size(Path::new(in_dir)))
+        .unwrap_or(config::BRANCHES_SIZE);
+    if !map_size.is_power_of_two()
+        || map_size < 1 << config::MAP_SIZE_POW2_MIN
+        || map_size > 1 << config::MAP_SIZE_POW2_MAX
+    {
+        panic!("The map size should be a power of two from 2^16 to 2^24.");
+    }
+    info!("Map size: {}", map_size);
+    let depot = Arc::new(Depot::new(
+        PathBuf::from(in_dir),
+        &work_dir,
+        SchedulePolicy::Fifo,
+    ));
+    let mut executor = Executor::new(
+        cmd.specify(1),
+        Arc::new(GlobalBranches::new(map_size)),
+        depot,
+        0,
+        true,
+        Arc::new(Mutex::new(0)),
+    );
+
+    let inputs = list_inputs(Path::new(in_dir));
+    let mut paths = vec![];
+    let mut traces = vec![];
+    let mut num_skipped = 0;
+    for path in inputs {
+        let buf = match read_from_file(&path) {
+            Some(buf) if buf.len() <= cmd.max_len => buf,
+            _ => {
+                num_skipped += 1;
+                continue;
+            }
+        };
+        let (status, _) = executor.run_calibrate(&buf);
+        if status != StatusType::Normal {
+            debug!("Skip {:?}: {:?}", path, status);
+            num_skipped += 1;
+            continue;
+        }
+        traces.push((buf.len(), to_tuples(&executor.branches.get_path())));
+        paths.push(path);
+    }
+    drop(executor);
+
+    let chosen = minimize(&traces);
+    for &i in &chosen {
+        let src = &paths[i];
+        let dst = out_dir.join(src.file_name().unwrap());
+        fs::copy(src, &dst).expect("Could not copy input.");
+    }
+    info!(
+        "Kept {} of {} inputs in {:?}, skipped {} that crashed, hung or could not be read",
+        chosen.len(),
+        paths.len(),
+        out_dir,
+        num_skipped
+    );
+
+    drop(cmd);
+    if let Err(e) = fs::remove_dir_all(&work_dir) {
+        warn!("Could not remove {:?}: {:?}", work_dir, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimize() {
+        let traces = vec![
+            (10, vec![1, 2, 3]),
+            (5, vec![1, 2]),
+            (7, vec![3]),
+            (20, vec![1, 2, 3, 4]),
+            (5, vec![2]),
+        ];
+        let mut chosen = minimize(&traces);
+        chosen.sort();
+        assert_eq!(chosen, vec![1, 2, 3]);
+        assert!(minimize(&vec![]).is_empty());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{check_dep, tmpfs};
+use fastgen_common::coverage_mode::CoverageMode;
 use std::{
     path::{Path, PathBuf},
     process::Command,
@@ -40,10 +41,19 @@ pub struct CommandOpt {
     pub track_path: String,
     pub is_stdin: bool,
     pub mem_limit: u64,
+    // ms
     pub time_limit: u64,
+    // ms
+    pub track_time_limit: u64,
     pub is_raw: bool,
     pub uses_asan: bool,
     pub ld_library: String,
+    // limits are enforced by cgroups below this one instead of RLIMIT_AS
+    pub cgroup_root: Option<PathBuf>,
+    // the longest input that is imported, generated or tracked
+    pub max_len: usize,
+    // how the fast binary counts coverage, see coverage_mode.rs
+    pub coverage_mode: CoverageMode,
 }
 
 impl CommandOpt {
@@ -53,6 +63,9 @@ impl CommandOpt {
         out_dir: &Path,
         mut mem_limit: u64,
         time_limit: u64,
+        track_time_limit: u64,
+        cgroup_root: Option<PathBuf>,
+        max_len: usize,
     ) -> Self {
         let tmp_dir = out_dir.join(TMP_DIR);
         tmpfs::create_tmpfs_dir(&tmp_dir);
@@ -85,7 +98,7 @@ impl CommandOpt {
         let main_bin = tmp_args[0].clone();
         let main_args: Vec<String> = tmp_args.drain(1..).collect();
         let uses_asan = check_dep::check_asan(&main_bin);
-        if uses_asan && mem_limit != 0 {
+        if uses_asan && mem_limit != 0 && cgroup_root.is_none() {
             warn!("The program compiled with ASAN, set MEM_LIMIT to 0 (unlimited)");
             mem_limit = 0;
         }
@@ -105,9 +118,13 @@ impl CommandOpt {
             is_stdin: !has_input_arg,
             mem_limit,
             time_limit,
+            track_time_limit,
             uses_asan,
             is_raw: true,
             ld_library,
+            cgroup_root,
+            max_len,
+            coverage_mode: CoverageMode::default(),
         }
     }
 
@@ -0,0 +1,306 @@
+// `--coordinate`: fastgen instances on one sync directory share what they
+// know about branches and split the seeds, through hash tables in a file of
+// the sync directory that they all map. Each instance still writes its own
+// queue, and imports the others' with the AFL sync.
+//
+// A seed is tracked by the instance that claims its content hash first, the
+// others put it aside and try again later. A claim holds the pid of its owner and a lease, and is
+// marked done once the seed is tracked. Another instance takes over a claim
+// that is not done if its owner died or the lease expired.
+//
+// The keys are hashed with FNV-1a, the instances may be different builds.
+
+use crate::checkpoint::BranchKey;
+use fastgen_common::{config, defs};
+use memmap::{MmapMut, MmapOptions};
+use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+use std::{
+    fs::OpenOptions,
+    mem::size_of,
+    path::Path,
+    slice,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const COORD_MAGIC: u64 = 0x6673_7467_636f_6f02;
+
+// the lease of a claim whose seed is tracked
+const CLAIM_DONE: u32 = u32::MAX;
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    num_branch_slots: u64,
+    num_seed_slots: u64,
+}
+
+#[repr(C)]
+struct BranchSlot {
+    key: AtomicU64,
+    hits: AtomicU32,
+    flipped: AtomicU32,
+}
+
+#[repr(C)]
+struct SeedSlot {
+    hash: AtomicU64,
+    // (owner pid << 32) | lease end in unix seconds, 0 if not claimed yet
+    claim: AtomicU64,
+}
+
+// The header is padded to 64 bytes, the slots stay aligned.
+const HEADER_SIZE: usize = 64;
+
+fn file_size() -> usize {
+    HEADER_SIZE
+        + config::COORD_BRANCH_SLOTS * size_of::<BranchSlot>()
+        + config::COORD_SEED_SLOTS * size_of::<SeedSlot>()
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn branch_hash(key: &BranchKey) -> u64 {
+    let mut bytes = Vec::with_capacity(28);
+    bytes.extend_from_slice(&key.0.to_le_bytes());
+    bytes.extend_from_slice(&key.1.to_le_bytes());
+    bytes.extend_from_slice(&key.2.to_le_bytes());
+    bytes.extend_from_slice(&key.3.to_le_bytes());
+    fnv1a(&bytes)
+}
+
+// The key of a seed in the table, the same in every instance.
+pub fn seed_hash(buf: &[u8]) -> u64 {
+    fnv1a(buf)
+}
+
+fn unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+fn claim_word(pid: u32, lease_end: u32) -> u64 {
+    ((pid as u64) << 32) | lease_end as u64
+}
+
+// EPERM is a live process of another user.
+fn pid_alive(pid: u32) -> bool {
+    match kill(Pid::from_raw(pid as i32), None) {
+        Err(Errno::ESRCH) => false,
+        _ => true,
+    }
+}
+
+// Linear probing, 0 marks an empty slot. Returns the slot of `key`, None if
+// the table is too full around it.
+fn probe<T>(slots: &[T], key: u64, slot_key: fn(&T) -> &AtomicU64) -> Option<&T> {
+    let key = if key == 0 { 1 } else { key };
+    let start = (key % slots.len() as u64) as usize;
+    for i in 0..config::COORD_MAX_PROBES {
+        let slot = &slots[(start + i) % slots.len()];
+        match slot_key(slot).compare_exchange(0, key, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return Some(slot),
+            Err(cur) if cur == key => return Some(slot),
+            Err(_) => {}
+        }
+    }
+    None
+}
+
+pub struct Coordinator {
+    mmap: MmapMut,
+    pid: u32,
+}
+
+// The tables are only accessed through atomics.
+unsafe impl Send for Coordinator {}
+unsafe impl Sync for Coordinator {}
+
+impl Coordinator {
+    pub fn open(sync_dir: &Path, instance: &str) -> Self {
+        let path = sync_dir.join(defs::COORD_FILE);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .expect("Could not open the coordination file.");
+        let len = file.metadata().expect("Could not stat the coordination file.").len();
+        // whoever comes first sizes it, the new space reads as zero
+        if len == 0 {
+            file.set_len(file_size() as u64)
+                .expect("Could not size the coordination file.");
+        } else if len != file_size() as u64 {
+            panic!("{:?} has a different size, remove it when no instance runs.", path);
+        }
+        let mut mmap = unsafe {
+            MmapOptions::new()
+                .map_mut(&file)
+                .expect("Could not map the coordination file.")
+        };
+        {
+            let header = unsafe { &mut *(mmap.as_mut_ptr() as *mut Header) };
+            if header.magic == 0 {
+                header.num_branch_slots = config::COORD_BRANCH_SLOTS as u64;
+                header.num_seed_slots = config::COORD_SEED_SLOTS as u64;
+                header.magic = COORD_MAGIC;
+            } else if header.magic != COORD_MAGIC {
+                panic!("{:?} is of another version, remove it when no instance runs.", path);
+            }
+        }
+        info!("Coordinating through {:?} as {}", path, instance);
+        Self {
+            mmap,
+            pid: std::process::id(),
+        }
+    }
+
+    fn branch_slots(&self) -> &[BranchSlot] {
+        unsafe {
+            let ptr = self.mmap.as_ptr().add(HEADER_SIZE) as *const BranchSlot;
+            slice::from_raw_parts(ptr, config::COORD_BRANCH_SLOTS)
+        }
+    }
+
+    fn seed_slots(&self) -> &[SeedSlot] {
+        unsafe {
+            let ptr = self.mmap.as_ptr().add(HEADER_SIZE) as *const BranchSlot;
+            let ptr = ptr.add(config::COORD_BRANCH_SLOTS) as *const SeedSlot;
+            slice::from_raw_parts(ptr, config::COORD_SEED_SLOTS)
+        }
+    }
+
+    fn branch(&self, key: &BranchKey) -> Option<&BranchSlot> {
+        probe(self.branch_slots(), branch_hash(key), |s| &s.key)
+    }
+
+    // Count a hit, returns the hits of all the instances, or 0 if the table
+    // is full.
+    pub fn hit(&self, key: &BranchKey) -> u32 {
+        match self.branch(key) {
+            Some(slot) => slot.hits.fetch_add(1, Ordering::Relaxed) + 1,
+            None => 0,
+        }
+    }
+
+    pub fn flip(&self, key: &BranchKey) {
+        if let Some(slot) = self.branch(key) {
+            slot.flipped.store(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_flipped(&self, key: &BranchKey) -> bool {
+        match self.branch(key) {
+            Some(slot) => slot.flipped.load(Ordering::Relaxed) != 0,
+            None => false,
+        }
+    }
+
+    // Whether this instance should track the seed, `hash` is its seed_hash.
+    // Its own claims stay valid, e.g. for requeued seeds. If the table is
+    // full, every instance tracks it.
+    pub fn claim_seed(&self, hash: u64) -> bool {
+        let slot = match probe(self.seed_slots(), hash, |s| &s.hash) {
+            Some(slot) => slot,
+            None => return true,
+        };
+        let now = unix_time();
+        let mine = claim_word(self.pid, now.saturating_add(config::COORD_CLAIM_LEASE));
+        let mut cur = slot.claim.load(Ordering::Acquire);
+        loop {
+            let (pid, lease_end) = ((cur >> 32) as u32, cur as u32);
+            if cur != 0 {
+                if lease_end == CLAIM_DONE {
+                    return false;
+                }
+                if pid != self.pid && lease_end >= now && pid_alive(pid) {
+                    return false;
+                }
+            }
+            // unclaimed, ours, or its owner is gone
+            match slot
+                .claim
+                .compare_exchange(cur, mine, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    if cur != 0 && pid != self.pid {
+                        info!("Took over seed {:016x} from pid {}", hash, pid);
+                    }
+                    return true;
+                }
+                Err(c) => cur = c,
+            }
+        }
+    }
+
+    pub fn seed_done(&self, hash: u64) -> bool {
+        match probe(self.seed_slots(), hash, |s| &s.hash) {
+            Some(slot) => slot.claim.load(Ordering::Acquire) as u32 == CLAIM_DONE,
+            None => false,
+        }
+    }
+
+    // The seed is tracked, or given up, nobody takes it over anymore.
+    pub fn finish_seed(&self, hash: u64) {
+        if let Some(slot) = probe(self.seed_slots(), hash, |s| &s.hash) {
+            slot.claim
+                .store(claim_word(self.pid, CLAIM_DONE), Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_coordinator() {
+        let sync_dir = std::env::temp_dir().join(format!("fastgen_coord_{}", std::process::id()));
+        std::fs::create_dir_all(&sync_dir).unwrap();
+        let a = Coordinator::open(&sync_dir, "fastgen01");
+        let mut b = Coordinator::open(&sync_dir, "fastgen02");
+        // init is always alive
+        b.pid = 1;
+
+        let key = (0x4005d0, 7, 1, 0);
+        assert_eq!(a.hit(&key), 1);
+        assert_eq!(b.hit(&key), 2);
+        assert!(!b.is_flipped(&key));
+        a.flip(&key);
+        assert!(b.is_flipped(&key));
+
+        assert!(b.claim_seed(42));
+        assert!(!a.claim_seed(42));
+        assert!(!a.seed_done(42));
+        assert!(b.claim_seed(42));
+        assert!(a.claim_seed(43));
+        a.finish_seed(43);
+        assert!(!b.claim_seed(43));
+        assert!(b.seed_done(43));
+
+        // the claims of a dead instance are taken over
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let mut dead = Coordinator::open(&sync_dir, "fastgen03");
+        dead.pid = child.id();
+        assert!(dead.claim_seed(44));
+        assert!(a.claim_seed(44));
+        assert!(!b.claim_seed(44));
+        std::fs::remove_dir_all(&sync_dir).unwrap();
+    }
+}
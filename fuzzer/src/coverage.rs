@@ -0,0 +1,216 @@
+// `fastgen report coverage`: line coverage of a queue as an lcov tracefile,
+// and as HTML if genhtml is installed. The edge ids of the branch map are
+// random and carry no source location, so every input is run once through a
+// sancov build of the target (-fsanitize=address -fsanitize-coverage=edge,
+// as in usenix/) and the covered PCs are mapped to lines with addr2line.
+
+use crate::{cmin::list_inputs, source_map::symbolize};
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as FmtWrite,
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+    time,
+};
+use wait_timeout::ChildExt;
+
+static COVERAGE_INFO_FILE: &str = "coverage.info";
+static HTML_DIR: &str = "html";
+static SANCOV_DIR: &str = "sancov";
+
+const SANCOV_MAGIC_64: u64 = 0xC0BF_FFFF_FFFF_FF64;
+const SANCOV_MAGIC_32: u64 = 0xC0BF_FFFF_FFFF_FF32;
+
+// A .sancov file is a magic word and then the PCs, 32 or 64 bits each.
+fn read_sancov(buf: &[u8]) -> Option<Vec<u64>> {
+    let mut r = buf;
+    let magic = r.read_u64::<LittleEndian>().ok()?;
+    let mut pcs = vec![];
+    match magic {
+        SANCOV_MAGIC_64 => {
+            while let Ok(pc) = r.read_u64::<LittleEndian>() {
+                pcs.push(pc);
+            }
+        }
+        SANCOV_MAGIC_32 => {
+            while let Ok(pc) = r.read_u32::<LittleEndian>() {
+                pcs.push(pc as u64);
+            }
+        }
+        _ => return None,
+    }
+    Some(pcs)
+}
+
+// file -> line -> the number of inputs that cover it
+type LineCounts = BTreeMap<String, BTreeMap<u32, usize>>;
+
+fn to_lcov(lines: &LineCounts) -> String {
+    let mut s = String::new();
+    for (file, counts) in lines {
+        let _ = writeln!(s, "TN:");
+        let _ = writeln!(s, "SF:{}", file);
+        for (line, count) in counts {
+            let _ = writeln!(s, "DA:{},{}", line, count);
+        }
+        let _ = writeln!(s, "LF:{}", counts.len());
+        let _ = writeln!(s, "LH:{}", counts.len());
+        let _ = writeln!(s, "end_of_record");
+    }
+    s
+}
+
+// Run one input, returns false if it timed out.
+fn run_input(pargs: &[String], input: &Path, sancov_dir: &Path, time_limit: u64) -> bool {
+    let has_input_arg = pargs.iter().any(|a| a == "@@");
+    let args: Vec<String> = pargs[1..]
+        .iter()
+        .map(|a| {
+            if a == "@@" {
+                input.to_str().unwrap().to_string()
+            } else {
+                a.clone()
+            }
+        })
+        .collect();
+    let stdin = if has_input_arg {
+        Stdio::null()
+    } else {
+        Stdio::from(fs::File::open(input).expect("Could not open input."))
+    };
+    let mut child = Command::new(&pargs[0])
+        .args(&args)
+        .env(
+            "ASAN_OPTIONS",
+            format!("coverage=1:coverage_dir={}", sancov_dir.to_str().unwrap()),
+        )
+        .stdin(stdin)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Could not run the coverage build.");
+    match child.wait_timeout(time::Duration::from_millis(time_limit)) {
+        Ok(Some(_)) => true,
+        _ => {
+            let _ = child.kill();
+            let _ = child.wait();
+            false
+        }
+    }
+}
+
+// The PCs of the binary the input covered, the files are removed.
+fn collect_pcs(sancov_dir: &Path, bin_name: &str) -> Vec<u64> {
+    let mut pcs = vec![];
+    for entry in sancov_dir.read_dir().expect("Could not read sancov directory.") {
+        let path = entry.expect("Could not read sancov directory.").path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        // libraries have files of their own
+        if name.starts_with(bin_name) && name.ends_with(".sancov") {
+            match fs::read(&path).ok().and_then(|buf| read_sancov(&buf)) {
+                Some(file_pcs) => pcs.extend(file_pcs),
+                None => warn!("Skip bad sancov file {:?}", path),
+            }
+        }
+        let _ = fs::remove_file(&path);
+    }
+    pcs.sort();
+    pcs.dedup();
+    pcs
+}
+
+pub fn coverage_main(in_dir: &str, out_dir: &str, pargs: Vec<String>, time_limit: u64, html: bool) {
+    pretty_env_logger::init();
+
+    let out_dir = Path::new(out_dir);
+    fs::create_dir(out_dir).expect("Output directory has existed!");
+    let sancov_dir = out_dir.join(SANCOV_DIR);
+    fs::create_dir(&sancov_dir).expect("Could not create sancov directory.");
+    let bin_name = Path::new(&pargs[0])
+        .file_name()
+        .expect("Bad program path.")
+        .to_string_lossy()
+        .to_string();
+
+    // pc -> the number of inputs that cover it
+    let mut pc_counts = HashMap::<u64, usize>::new();
+    let inputs = list_inputs(Path::new(in_dir));
+    let mut num_timeouts = 0;
+    for input in &inputs {
+        if !run_input(&pargs, input, &sancov_dir, time_limit) {
+            debug!("{:?} timed out", input);
+            num_timeouts += 1;
+        }
+        for pc in collect_pcs(&sancov_dir, &bin_name) {
+            *pc_counts.entry(pc).or_insert(0) += 1;
+        }
+    }
+    let _ = fs::remove_dir(&sancov_dir);
+
+    let mut pcs: Vec<u64> = pc_counts.keys().cloned().collect();
+    pcs.sort();
+    let locs = symbolize(&pargs[0], &pcs).expect("Could not run addr2line.");
+    let mut lines = LineCounts::new();
+    let mut num_unknown = 0;
+    for (pc, loc) in pcs.iter().zip(locs) {
+        match loc {
+            Some(loc) => {
+                let count = lines.entry(loc.file).or_default().entry(loc.line).or_insert(0);
+                *count = std::cmp::max(*count, pc_counts[pc]);
+            }
+            None => num_unknown += 1,
+        }
+    }
+
+    let info_path = out_dir.join(COVERAGE_INFO_FILE);
+    fs::write(&info_path, to_lcov(&lines)).expect("Could not write coverage.info.");
+    info!(
+        "{} inputs ({} timed out) cover {} edges, {} lines in {} files, {} edges without debug info",
+        inputs.len(),
+        num_timeouts,
+        pcs.len(),
+        lines.values().map(|counts| counts.len()).sum::<usize>(),
+        lines.len(),
+        num_unknown
+    );
+
+    if html {
+        let ret = Command::new("genhtml")
+            .arg("-q")
+            .arg("-o")
+            .arg(out_dir.join(HTML_DIR))
+            .arg(&info_path)
+            .status();
+        match ret {
+            Ok(status) if status.success() => info!("Wrote {:?}", out_dir.join(HTML_DIR)),
+            _ => warn!("genhtml failed, is lcov installed? {:?} is still there", info_path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sancov_lcov() {
+        let mut buf = SANCOV_MAGIC_64.to_le_bytes().to_vec();
+        buf.extend_from_slice(&0x4005d0u64.to_le_bytes());
+        buf.extend_from_slice(&0x4005e8u64.to_le_bytes());
+        assert_eq!(read_sancov(&buf), Some(vec![0x4005d0, 0x4005e8]));
+        let mut buf = SANCOV_MAGIC_32.to_le_bytes().to_vec();
+        buf.extend_from_slice(&0x1234u32.to_le_bytes());
+        assert_eq!(read_sancov(&buf), Some(vec![0x1234]));
+        assert_eq!(read_sancov(&[0; 8]), None);
+
+        let mut lines = LineCounts::new();
+        lines.entry("a.c".to_string()).or_default().insert(3, 2);
+        lines.entry("a.c".to_string()).or_default().insert(5, 1);
+        assert_eq!(
+            to_lcov(&lines),
+            "TN:\nSF:a.c\nDA:3,2\nDA:5,1\nLF:2\nLH:2\nend_of_record\n"
+        );
+    }
+}
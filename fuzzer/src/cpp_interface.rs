@@ -8,6 +8,9 @@ extern "C" {
     pub fn qsym_filter(session: u64, addr: u64, direction: bool) -> bool;
     pub fn start_session() -> u64;
     pub fn end_session(s: u64);
+    pub fn filter_map_size() -> u32;
+    pub fn get_filter_state(trace: *mut u8, context: *mut u8);
+    pub fn set_filter_state(trace: *const u8, context: *const u8);
     //pub fn get_next_input(input: *mut u8, addr: *mut u64, ctx: *mut u64,
     //      order: *mut u32, fid: *mut u32, direction: *mut u64,
     //      bid: *mut u32, sctx: *mut u32, is_cmp: *mut bool, predicate: *mut u32, target_cond: *mut u64, cons_hash: *mut u32, size: usize);
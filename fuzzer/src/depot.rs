@@ -1,64 +1,136 @@
 use crate::depot_dir::*;
 use crate::file::*;
+use crate::input_cache::InputCache;
+use crate::provenance::Provenance;
+use crate::scheduler::{new_scheduler, SchedulePolicy, Seed, SeedScheduler, UNKNOWN_EDGES};
 use crate::status_type::StatusType;
+use fastgen_common::{config, defs};
 use rand;
 use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
     fs,
-    io::prelude::*,
+    hash::{Hash, Hasher},
+    io::{self, prelude::*},
     path::{Path, PathBuf},
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
 };
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-// https://crates.io/crates/priority-queue
 
 pub struct Depot {
     pub num_inputs: AtomicUsize,
     pub num_hangs: AtomicUsize,
     pub num_crashes: AtomicUsize,
-    pub next_id: AtomicUsize,
+    pub num_ooms: AtomicUsize,
+    scheduler: Mutex<Box<dyn SeedScheduler>>,
+    // handed out but not done yet, they go back into the queue on resume
+    in_flight: Mutex<HashMap<usize, Seed>>,
+    // content hashes of everything saved, so the same bytes are saved once
+    hashes: Mutex<HashSet<u64>>,
+    cache: Mutex<InputCache>,
+    // the file names of the queue, they have AFL's descriptions after the id
+    input_paths: RwLock<HashMap<usize, PathBuf>>,
     pub dirs: DepotDir,
 }
 
+pub fn hash_buf(buf: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    buf.hash(&mut hasher);
+    hasher.finish()
+}
+
 impl Depot {
-    pub fn new(in_dir: PathBuf, out_dir: &Path) -> Self {
+    pub fn new(in_dir: PathBuf, out_dir: &Path, policy: SchedulePolicy) -> Self {
         Self {
             num_inputs: AtomicUsize::new(0),
             num_hangs: AtomicUsize::new(0),
             num_crashes: AtomicUsize::new(0),
-            next_id: AtomicUsize::new(0),
+            num_ooms: AtomicUsize::new(0),
+            scheduler: Mutex::new(new_scheduler(policy)),
+            in_flight: Mutex::new(HashMap::new()),
+            hashes: Mutex::new(HashSet::new()),
+            cache: Mutex::new(InputCache::new(config::INPUT_CACHE_SIZE)),
+            input_paths: RwLock::new(HashMap::new()),
             dirs: DepotDir::new(in_dir, out_dir),
         }
     }
 
-    fn save_input(status: &StatusType, buf: &Vec<u8>, num: &AtomicUsize, dir: &Path) -> usize {
+    fn save_input(
+        status: &StatusType,
+        buf: &Vec<u8>,
+        num: &AtomicUsize,
+        dir: &Path,
+        desc: &str,
+    ) -> (usize, PathBuf) {
         let mut id = num.load(Ordering::Acquire);
         trace!("Find {} th new {:?} input", id, status,);
-        let new_path = get_file_name(dir, id);
+        let new_path = get_afl_file_name(dir, id, desc);
         let mut f = fs::File::create(new_path.as_path()).expect("Could not save new input file.");
         f.write_all(buf)
             .expect("Could not write seed buffer to file.");
         f.flush().expect("Could not flush file I/O.");
         id = id + 1;
         num.store(id, Ordering::Release);
-        id
+        (id, new_path)
     }
 
-    pub fn save(&self, status: StatusType, buf: &Vec<u8>) -> usize {
+    // `num_edges` is the length of the input's path, for scheduling, and
+    // `desc` goes into the file name, see Provenance::afl_desc.
+    // Returns 0 if nothing was saved, e.g. the same bytes were saved before.
+    pub fn save(&self, status: StatusType, buf: &Vec<u8>, num_edges: usize, desc: &str) -> usize {
+        match status {
+            StatusType::Normal | StatusType::Timeout | StatusType::Crash | StatusType::Oom => {}
+            _ => return 0,
+        }
+        if !self.hashes.lock().unwrap().insert(hash_buf(buf)) {
+            debug!("Skip a {:?} input that was saved before", status);
+            return 0;
+        }
         match status {
             StatusType::Normal => {
-                Self::save_input(&status, buf, &self.num_inputs, &self.dirs.inputs_dir)
+                let (id, path) =
+                    Self::save_input(&status, buf, &self.num_inputs, &self.dirs.inputs_dir, desc);
+                self.input_paths.write().unwrap().insert(id - 1, path);
+                self.cache.lock().unwrap().insert(id - 1, buf);
+                let seed = Seed::new(id - 1, buf.len(), num_edges);
+                self.scheduler.lock().unwrap().push(seed);
+                id
             }
             StatusType::Timeout => {
-                Self::save_input(&status, buf, &self.num_hangs, &self.dirs.hangs_dir)
+                Self::save_input(&status, buf, &self.num_hangs, &self.dirs.hangs_dir, desc).0
             }
             StatusType::Crash => {
-                Self::save_input(&status, buf, &self.num_crashes, &self.dirs.crashes_dir)
+                Self::save_input(&status, buf, &self.num_crashes, &self.dirs.crashes_dir, desc).0
+            }
+            StatusType::Oom => {
+                Self::save_input(&status, buf, &self.num_ooms, &self.dirs.ooms_dir, desc).0
             }
             _ => 0,
         }
     }
 
+    // `id` is the one returned by save, minus 1.
+    pub fn save_meta(&self, status: StatusType, id: usize, prov: &Provenance) {
+        let dir = match status {
+            StatusType::Normal => defs::INPUTS_DIR,
+            StatusType::Timeout => defs::HANGS_DIR,
+            StatusType::Crash => defs::CRASHES_DIR,
+            StatusType::Oom => defs::OOMS_DIR,
+            _ => return,
+        };
+        let path = get_file_name(&self.dirs.meta_dir.join(dir), id).with_extension("json");
+        if let Err(e) = fs::write(&path, prov.to_json(id, status)) {
+            warn!("Could not save provenance {:?}: {:?}", path, e);
+        }
+    }
+
+    pub fn contains(&self, hash: u64) -> bool {
+        self.hashes.lock().unwrap().contains(&hash)
+    }
+
     pub fn empty(&self) -> bool {
         self.num_inputs.load(Ordering::Relaxed) == 0
     }
@@ -68,39 +140,147 @@ impl Depot {
     }
 
     pub fn get_input_buf(&self, id: usize) -> Option<Vec<u8>> {
-        let path = get_file_name(&self.dirs.inputs_dir, id);
-        read_from_file(&path)
+        if let Some(buf) = self.cache.lock().unwrap().get(id) {
+            return Some(buf);
+        }
+        let path = self.get_input_path(id);
+        let buf = read_from_file(&path)?;
+        self.cache.lock().unwrap().insert(id, &buf);
+        Some(buf)
+    }
+
+    // (hits, misses, bytes cached)
+    pub fn get_cache_stats(&self) -> (usize, usize, usize) {
+        let cache = self.cache.lock().unwrap();
+        let (hits, misses) = cache.stats();
+        (hits, misses, cache.size())
     }
 
     pub fn get_input_path(&self, id: usize) -> PathBuf {
-        get_file_name(&self.dirs.inputs_dir, id)
+        match self.input_paths.read().unwrap().get(&id) {
+            Some(path) => path.clone(),
+            None => get_file_name(&self.dirs.inputs_dir, id),
+        }
+    }
+
+    // Copy rather than move: ids in the queue have to stay dense.
+    pub fn save_track_failure(&self, id: usize) {
+        let src = self.get_input_path(id);
+        let dst = self.dirs.track_failures_dir.join(src.file_name().unwrap());
+        if let Err(e) = fs::copy(&src, &dst) {
+            warn!("Could not save track failure {:?}: {:?}", src, e);
+        }
     }
 
     pub fn get_num_inputs(&self) -> usize {
         self.num_inputs.load(Ordering::Relaxed)
     }
 
-    pub fn get_next_input(&self) -> Option<usize> {
-        let cur_id = self.next_id.load(Ordering::Relaxed);
-        if cur_id < self.get_num_inputs() {
-            let retval = self.next_id.compare_exchange(cur_id, cur_id + 1, Ordering::Relaxed, Ordering::Relaxed).ok();
+    pub fn get_num_queued(&self) -> usize {
+        self.scheduler.lock().unwrap().len()
+    }
 
-            if let Some(id) = retval {
-                let mut progress = Vec::new();
-                progress.write_u32::<LittleEndian>(id as u32).unwrap();
-                std::fs::write("ce_progress", &progress)
-                    .map_err(|err| println!("{:?}", err))
-                    .ok();
+    pub fn get_next_input(&self) -> Option<Seed> {
+        let retval = self.scheduler.lock().unwrap().pop();
+        if let Some(seed) = retval {
+            self.in_flight.lock().unwrap().insert(seed.id, seed);
+        }
+        retval
+    }
+
+    pub fn done(&self, id: usize) {
+        self.in_flight.lock().unwrap().remove(&id);
+    }
+
+    // Hand a seed back, it comes after all the seeds that were never tried.
+    // Returns false if it was requeued too often and is dropped.
+    pub fn requeue(&self, mut seed: Seed) -> bool {
+        self.done(seed.id);
+        if seed.requeued >= config::MAX_REQUEUE {
+            info!("Seed {} was requeued {} times, dropping it", seed.id, seed.requeued);
+            return false;
+        }
+        seed.requeued += 1;
+        self.scheduler.lock().unwrap().push(seed);
+        true
+    }
+
+    fn counters(&self) -> [&AtomicUsize; 4] {
+        [
+            &self.num_inputs,
+            &self.num_hangs,
+            &self.num_crashes,
+            &self.num_ooms,
+        ]
+    }
+
+    // The counters and the queued seeds, see checkpoint.rs.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for num in self.counters().iter() {
+            w.write_u64::<LittleEndian>(num.load(Ordering::Relaxed) as u64)?;
+        }
+        let mut seeds = self.scheduler.lock().unwrap().seeds();
+        seeds.extend(self.in_flight.lock().unwrap().values().cloned());
+        w.write_u64::<LittleEndian>(seeds.len() as u64)?;
+        for seed in seeds {
+            for v in [seed.id, seed.len, seed.num_edges, seed.requeued].iter() {
+                w.write_u64::<LittleEndian>(*v as u64)?;
             }
-            return retval;
         }
-        None
+        Ok(())
     }
 
-    pub fn load_cached_next_id(&self) {
-        let progress_data = std::fs::read("ce_progress").unwrap();
-        let id: u32 = (&progress_data[..]).read_u32::<LittleEndian>().unwrap();
-        println!("restarting scan from id {}", id);
-        self.next_id.store(id as usize, Ordering::Relaxed);
+    pub fn read_from<R: Read>(&self, r: &mut R) -> io::Result<()> {
+        for num in self.counters().iter() {
+            num.store(r.read_u64::<LittleEndian>()? as usize, Ordering::Relaxed);
+        }
+        let num_seeds = r.read_u64::<LittleEndian>()?;
+        let mut scheduler = self.scheduler.lock().unwrap();
+        for _ in 0..num_seeds {
+            let mut seed = Seed::new(0, 0, 0);
+            seed.id = r.read_u64::<LittleEndian>()? as usize;
+            seed.len = r.read_u64::<LittleEndian>()? as usize;
+            seed.num_edges = r.read_u64::<LittleEndian>()? as usize;
+            seed.requeued = r.read_u64::<LittleEndian>()? as usize;
+            scheduler.push(seed);
+        }
+
+        // inputs saved after the checkpoint was written
+        let dirs = [
+            &self.dirs.inputs_dir,
+            &self.dirs.hangs_dir,
+            &self.dirs.crashes_dir,
+            &self.dirs.ooms_dir,
+        ];
+        for (num, dir) in self.counters().iter().zip(dirs.iter()) {
+            let mut id = num.load(Ordering::Relaxed);
+            let index = index_dir(dir);
+            // the content hashes are not in the checkpoint, rebuild them
+            let mut hashes = self.hashes.lock().unwrap();
+            for i in 0..id {
+                if let Some(Ok(buf)) = index.get(&i).map(fs::read) {
+                    hashes.insert(hash_buf(&buf));
+                }
+            }
+            while let Some(path) = index.get(&id) {
+                let len = match fs::read(path) {
+                    Ok(buf) => {
+                        hashes.insert(hash_buf(&buf));
+                        buf.len()
+                    }
+                    Err(_) => break,
+                };
+                if *dir == &self.dirs.inputs_dir {
+                    // not run yet, it must not outrank the others with fewest_edges
+                    scheduler.push(Seed::new(id, len, UNKNOWN_EDGES));
+                }
+                id += 1;
+            }
+            num.store(id, Ordering::Relaxed);
+            if *dir == &self.dirs.inputs_dir {
+                *self.input_paths.write().unwrap() = index;
+            }
+        }
+        Ok(())
     }
 }
@@ -9,6 +9,10 @@ pub struct DepotDir {
     pub inputs_dir: PathBuf,
     pub hangs_dir: PathBuf,
     pub crashes_dir: PathBuf,
+    pub ooms_dir: PathBuf,
+    pub track_failures_dir: PathBuf,
+    // provenance sidecars, with a sub-directory for each of the above
+    pub meta_dir: PathBuf,
     pub seeds_dir: PathBuf,
 }
 
@@ -17,15 +21,34 @@ impl DepotDir {
         let inputs_dir = out_dir.join(defs::INPUTS_DIR);
         let hangs_dir = out_dir.join(defs::HANGS_DIR);
         let crashes_dir = out_dir.join(defs::CRASHES_DIR);
+        let ooms_dir = out_dir.join(defs::OOMS_DIR);
+        let track_failures_dir = out_dir.join(defs::TRACK_FAILURES_DIR);
+        let meta_dir = out_dir.join(defs::META_DIR);
 
-        fs::create_dir(&crashes_dir).unwrap();
-        fs::create_dir(&hangs_dir).unwrap();
-        fs::create_dir(&inputs_dir).unwrap();
+        // they already exist when resuming
+        fs::create_dir_all(&crashes_dir).unwrap();
+        fs::create_dir_all(&hangs_dir).unwrap();
+        fs::create_dir_all(&inputs_dir).unwrap();
+        fs::create_dir_all(&ooms_dir).unwrap();
+        fs::create_dir_all(&track_failures_dir).unwrap();
+        for dir in [
+            defs::INPUTS_DIR,
+            defs::HANGS_DIR,
+            defs::CRASHES_DIR,
+            defs::OOMS_DIR,
+        ]
+        .iter()
+        {
+            fs::create_dir_all(meta_dir.join(dir)).unwrap();
+        }
 
         Self {
             inputs_dir,
             hangs_dir,
             crashes_dir,
+            ooms_dir,
+            track_failures_dir,
+            meta_dir,
             seeds_dir,
         }
     }
@@ -3,7 +3,14 @@ use crate::status_type::StatusType;
 
 use crate::forksrv::Forksrv;
 use crate::pipe_fd::PipeFd;
-
+use crate::fifo::read_pipe;
+use crate::watchdog::{TrackChild, TrackExit};
+
+use crate::cgroup::{Cgroup, ConfigCgroup};
+use crate::fuzz_stats::FuzzStats;
+use crate::provenance::Provenance;
+use crate::source_map::SourceMap;
+use crate::timeline::{Timeline, TimelineRecord};
 use crate::{branches, command, depot, shm_conds};
 use fastgen_common::{config, defs};
 
@@ -18,7 +25,7 @@ use std::{
         atomic::{compiler_fence, Ordering},
         Arc, Mutex,
     },
-    time,
+    thread, time,
 };
 use wait_timeout::ChildExt;
 
@@ -66,7 +73,16 @@ pub struct Executor {
     pub has_new_path: bool,
     pub shmid: i32,
     pub fl: Arc<Mutex<u32>>,
-    executor_timeout: u64
+    cgroup: Option<Cgroup>,
+    track_cgroup: Option<Cgroup>,
+    // where the input of the next run comes from, recorded if it is saved
+    pub provenance: Option<Provenance>,
+    // counts the runs and the saved inputs for fuzzer_stats
+    pub stats: Option<Arc<FuzzStats>>,
+    // logs the saved inputs with the coverage at that time
+    pub timeline: Option<Arc<Timeline>>,
+    // the branch locations in the provenance of saved solutions
+    pub source_map: Option<Arc<SourceMap>>,
 }
 
 impl Executor {
@@ -77,7 +93,6 @@ impl Executor {
         shmid: i32,
         is_grading: bool,
         forklock: Arc<Mutex<u32>>,
-        executor_timeout: u64
     ) -> Self {
         // ** Share Memory **
         let branches = branches::Branches::new(global_branches);
@@ -97,6 +112,14 @@ impl Executor {
             defs::BRANCHES_SHM_ENV_VAR.to_string(),
             branches.get_id().to_string(),
         );
+        envs.insert(
+            defs::MAP_SIZE_ENV_VAR.to_string(),
+            branches.get_size().to_string(),
+        );
+        envs.insert(
+            defs::COVERAGE_MODE_ENV_VAR.to_string(),
+            cmd.coverage_mode.to_string(),
+        );
         envs.insert(
             defs::COND_STMT_ENV_VAR.to_string(),
             t_conds.get_id().to_string(),
@@ -106,6 +129,22 @@ impl Executor {
             cmd.ld_library.clone(),
         );
 
+        let (cgroup, track_cgroup) = match cmd.cgroup_root {
+            Some(ref root) => (
+                Cgroup::new(
+                    root.join(format!("exec_{}", cmd.id)),
+                    cmd.mem_limit,
+                    config::CGROUP_PIDS_MAX,
+                ),
+                Cgroup::new(
+                    root.join(format!("track_{}", cmd.id)),
+                    config::MEM_LIMIT_TRACK,
+                    config::CGROUP_PIDS_MAX,
+                ),
+            ),
+            None => (None, None),
+        };
+
         let fd = pipe_fd::PipeFd::new(&cmd.out_file);
         let forksrv = if is_grading {
             forksrv::Forksrv::new(
@@ -116,7 +155,8 @@ impl Executor {
                 cmd.is_stdin,
                 cmd.uses_asan,
                 cmd.time_limit,
-                cmd.mem_limit,
+                if cgroup.is_some() { 0 } else { cmd.mem_limit },
+                cgroup.as_ref(),
                 forklock.clone(),
             )
         } else {
@@ -135,7 +175,12 @@ impl Executor {
             has_new_path: false,
             shmid,
             fl: forklock.clone(),
-            executor_timeout
+            cgroup,
+            track_cgroup,
+            provenance: None,
+            stats: None,
+            timeline: None,
+            source_map: None,
         }
     }
 
@@ -152,13 +197,14 @@ impl Executor {
             self.cmd.is_stdin,
             self.cmd.uses_asan,
             self.cmd.time_limit,
-            self.cmd.mem_limit,
+            if self.cgroup.is_some() { 0 } else { self.cmd.mem_limit },
+            self.cgroup.as_ref(),
             self.fl.clone(),
         );
         self.forksrv = fs;
     }
 
-    pub fn track(&mut self, id: usize, buf: &Vec<u8>) -> (std::process::Child, RawFd) {
+    pub fn track(&mut self, id: usize, buf: &Vec<u8>) -> (TrackChild, RawFd) {
         //FIXME
         let e = format!(
             "taint_file={} tid={} shmid={} pipeid=200",
@@ -173,8 +219,7 @@ impl Executor {
         let (child, read_end) = self.run_track(
             &self.cmd.track,
             config::MEM_LIMIT_TRACK,
-            //self.cmd.time_limit *
-            config::TIME_LIMIT_TRACK,
+            self.cmd.track_time_limit,
         );
         compiler_fence(Ordering::SeqCst);
         (child, read_end)
@@ -190,14 +235,85 @@ impl Executor {
         */
     }
 
+    // Track without solving, e.g. to tell flaky tracking failures from deterministic ones.
+    pub fn track_drain(&mut self, id: usize, buf: &Vec<u8>) -> TrackExit {
+        let (child, read_end) = self.track(id, buf);
+        // drain the pipe, otherwise the target blocks once it is full
+        let handle = thread::spawn(move || {
+            read_pipe(read_end);
+        });
+        if handle.join().is_err() {
+            error!("Error happened in draining thread!");
+        }
+        self.finish_track(child)
+    }
+
+    pub fn finish_track(&mut self, child: TrackChild) -> TrackExit {
+        let mut exit = child.finish();
+        if let Some(ref mut cg) = self.track_cgroup {
+            exit.oom = cg.check_oom();
+        }
+        exit
+    }
+
+    // Re-run an input with new coverage, and mark the edges that vary between
+    // runs as unstable so that has_new ignores them.
+    fn check_stability(&mut self, buf: &Vec<u8>, path: &Vec<(usize, u8)>) {
+        let mut runs = config::CALIBRATE_RUNS;
+        let mut i = 1;
+        while i < runs {
+            if self.run_inner(buf) != StatusType::Normal {
+                break;
+            }
+            if self.branches.mark_unstable(path) > 0 {
+                // look harder once something varies, like AFL
+                runs = config::MAX_INVARIABLE_NUM;
+            }
+            i += 1;
+        }
+    }
+
     fn do_if_has_new(&mut self, buf: &Vec<u8>, status: StatusType) -> (bool, usize) {
+        let mut prov = self.provenance.take().unwrap_or_default();
         // new edge: one byte in bitmap
-        let has_new_path = self.branches.has_new(status);
+        let path = self.branches.get_path();
+        let num_new_edges = if self.branches.is_new(status, &path) {
+            if status == StatusType::Normal {
+                self.check_stability(buf, &path);
+            }
+            self.branches.count_new_in(status, &path)
+        } else {
+            None
+        };
+        let mut has_new_path = num_new_edges.is_some();
         let mut new_id = 0;
 
         if has_new_path {
-            self.has_new_path = true;
-            new_id = self.depot.save(status, &buf) - 1;
+            let desc = prov.afl_desc(num_new_edges.unwrap_or(0) > 0);
+            match self.depot.save(status, &buf, path.len(), &desc) {
+                // the same bytes were saved before
+                0 => has_new_path = false,
+                id => {
+                    self.has_new_path = true;
+                    new_id = id - 1;
+                    if let Some(ref source_map) = self.source_map {
+                        prov.locate(source_map);
+                    }
+                    self.depot.save_meta(status, new_id, &prov);
+                    if let Some(ref stats) = self.stats {
+                        stats.saved(status);
+                    }
+                    if let Some(ref timeline) = self.timeline {
+                        timeline.record(&TimelineRecord {
+                            id: new_id,
+                            status,
+                            stage: prov.source,
+                            total_edges: self.branches.get_num_edges(),
+                            new_edges: num_new_edges.unwrap_or(0),
+                        });
+                    }
+                }
+            }
         }
         (has_new_path, new_id)
     }
@@ -232,13 +348,30 @@ impl Executor {
         ret
     }
 
+    // Run without saving anything, and report how long the execution took.
+    pub fn run_calibrate(&mut self, buf: &Vec<u8>) -> (StatusType, time::Duration) {
+        self.run_init();
+        let t_start = time::Instant::now();
+        let status = self.run_inner(buf);
+        let used_t = t_start.elapsed();
+        (self.check_timeout(status), used_t)
+    }
+
+    // The signal that killed the target in the last run, a rough crash signature.
+    pub fn last_signal(&self) -> Option<i32> {
+        self.forksrv.as_ref().and_then(|fs| fs.last_signal())
+    }
+
     pub fn get_cond(&mut self) -> u64 {
         return self.t_conds.cond.condition;
     }
 
-    pub fn run_norun(&mut self, buf: &Vec<u8>) {
-        let status = StatusType::Normal;
-        self.depot.save(status, &buf);
+    // Run an imported input, it is only saved if it has new coverage.
+    pub fn run_import(&mut self, buf: &Vec<u8>) -> (StatusType, bool) {
+        self.run_init();
+        let status = self.run_inner(buf);
+        let (has_new_path, _) = self.do_if_has_new(buf, status);
+        (self.check_timeout(status), has_new_path)
     }
 
     fn run_init(&mut self) {
@@ -271,6 +404,10 @@ impl Executor {
 
         self.branches.clear_trace();
 
+        if let Some(ref stats) = self.stats {
+            stats.num_execs.fetch_add(1, Ordering::Relaxed);
+        }
+
         compiler_fence(Ordering::SeqCst);
         let mut ret_status = StatusType::Error;
         if let Some(ref mut fs) = self.forksrv {
@@ -285,6 +422,14 @@ impl Executor {
         };
         compiler_fence(Ordering::SeqCst);
 
+        if ret_status == StatusType::Crash {
+            if let Some(ref mut cg) = self.cgroup {
+                if cg.check_oom() {
+                    ret_status = StatusType::Oom;
+                }
+            }
+        }
+
         ret_status
     }
 
@@ -320,7 +465,7 @@ impl Executor {
             .spawn()
             .expect("Could not run target");
 
-        let timeout = time::Duration::from_secs(time_limit);
+        let timeout = time::Duration::from_millis(time_limit);
         let ret = match child.wait_timeout(timeout) {
             //let ret = match child.try_wait() {
             Ok(Some(status)) => {
@@ -357,30 +502,29 @@ impl Executor {
         target: &(String, Vec<String>),
         mem_limit: u64,
         time_limit: u64,
-    ) -> (std::process::Child, RawFd) {
+    ) -> (TrackChild, RawFd) {
         let guard = self.fl.lock().unwrap();
         let (read_end, write_end) = pipe().unwrap();
-        let mut cmd = Command::new("timeout");
-        let mut child = cmd
-            .arg(format!("{}", self.executor_timeout))
-            .arg(&target.0)
+        let mut cmd = Command::new(&target.0);
+        let child = cmd
             .args(&target.1)
             //  .stdin(Stdio::null())
             .env_clear()
             .envs(&self.envs)
             .stdout(Stdio::null())
             .stderr(Stdio::null())
-            .mem_limit(mem_limit.clone())
+            .mem_limit(if self.track_cgroup.is_some() { 0 } else { mem_limit })
             .setsid()
+            .join_cgroup(self.track_cgroup.as_ref())
             .setpipe(read_end, write_end)
             .pipe_stdin(self.fd.as_raw_fd(), self.cmd.is_stdin)
             .spawn()
             .expect("Could not run target");
 
         close(write_end);
-        (child, read_end)
+        (TrackChild::new(child, time_limit), read_end)
         /*
-            let timeout = time::Duration::from_secs(time_limit);
+            let timeout = time::Duration::from_millis(time_limit);
             let ret = match child.wait_timeout(timeout) {
               Ok(Some(status)) => {
                 if let Some(status_code) = status.code() {
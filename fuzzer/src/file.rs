@@ -1,6 +1,7 @@
 use std::thread;
 use std::time;
 use std::{
+    collections::HashMap,
     fs,
     io::prelude::*,
     path::{Path, PathBuf},
@@ -11,6 +12,35 @@ pub fn get_file_name(dir: &Path, id: usize) -> PathBuf {
     dir.join(file_name)
 }
 
+// "id:NNNNNN,<desc>" like AFL's names, AFL++ syncs any "id:" file.
+pub fn get_afl_file_name(dir: &Path, id: usize, desc: &str) -> PathBuf {
+    if desc.is_empty() {
+        return get_file_name(dir, id);
+    }
+    dir.join(format!("id:{:06},{}", id, desc))
+}
+
+// The id of "id:NNNNNN" or "id:NNNNNN,...", which may have more digits.
+pub fn parse_file_id(name: &str) -> Option<usize> {
+    let s = name.strip_prefix("id:")?;
+    let end = s.find(',').unwrap_or(s.len());
+    s[..end].parse().ok()
+}
+
+// id -> path of the inputs in `dir`, whatever their descriptions are.
+pub fn index_dir(dir: &Path) -> HashMap<usize, PathBuf> {
+    let mut index = HashMap::new();
+    if let Ok(entries) = dir.read_dir() {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(id) = parse_file_id(&name) {
+                index.insert(id, entry.path());
+            }
+        }
+    }
+    index
+}
+
 pub fn read_from_file(path: &Path) -> Option<Vec<u8>> {
     let mut file;
     let mut i = 0;
@@ -44,3 +74,22 @@ pub fn read_from_file(path: &Path) -> Option<Vec<u8>> {
 
     Some(buf)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_names() {
+        let dir = Path::new("queue");
+        assert_eq!(get_afl_file_name(dir, 7, ""), dir.join("id:000007"));
+        assert_eq!(
+            get_afl_file_name(dir, 7, "src:000003,op:fastgen"),
+            dir.join("id:000007,src:000003,op:fastgen")
+        );
+        assert_eq!(parse_file_id("id:000007"), Some(7));
+        assert_eq!(parse_file_id("id:1234567,sync:afl,src:000001"), Some(1234567));
+        assert_eq!(parse_file_id("README.txt"), None);
+        assert_eq!(parse_file_id("id:abc"), None);
+    }
+}
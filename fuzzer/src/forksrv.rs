@@ -19,6 +19,7 @@ use std::{
     time::Duration,
 };
 
+use crate::cgroup::{Cgroup, ConfigCgroup};
 use crate::status_type::StatusType;
 
 use nix::{
@@ -121,9 +122,12 @@ impl ConfigTarget for Command {
 pub struct Forksrv {
     uses_asan: bool,
     is_stdin: bool,
+    time_limit: u64,
     child_pid: Pid,
     ctl_write_end: RawFd,
     st_read_end: RawFd,
+    // the signal that killed the last child, if it was killed by one
+    last_signal: Option<i32>,
 }
 
 impl Forksrv {
@@ -136,6 +140,7 @@ impl Forksrv {
         uses_asan: bool,
         time_limit: u64,
         mem_limit: u64,
+        cgroup: Option<&Cgroup>,
         forklock: Arc<Mutex<u32>>,
     ) -> Option<Self> {
         debug!("socket_path: {:?}", socket_path);
@@ -158,6 +163,7 @@ impl Forksrv {
             .setlimit(mem_limit)
             .mem_limit(mem_limit.clone())
             .setsid()
+            .join_cgroup(cgroup)
             .pipe_stdin(fd, is_stdin)
             .setpipe(st_read_end, st_write_end, ctl_read_end, ctl_write_end)
             .spawn()
@@ -178,9 +184,11 @@ impl Forksrv {
         Some(Forksrv {
             uses_asan,
             is_stdin,
+            time_limit,
             child_pid: Pid::from_raw(0),
             ctl_write_end,
             st_read_end,
+            last_signal: None,
         })
     }
 
@@ -241,7 +249,12 @@ impl Forksrv {
         self.child_pid = child_pid;
     }
 
+    pub fn last_signal(&self) -> Option<i32> {
+        self.last_signal
+    }
+
     pub fn run(&mut self) -> StatusType {
+        self.last_signal = None;
         if let Ok(send_len) = self.write_ctl(&FORKSRV_NEW_CHILD) {
             if send_len != 4 {
                 warn!("Unable to request new process from fork server (OOM?)");
@@ -269,11 +282,14 @@ impl Forksrv {
             return StatusType::Error;
         }
 
-        let mut timeout = TimeVal::seconds(1);
+        let mut timeout = TimeVal::milliseconds(self.time_limit as i64);
 
         if let Ok(Some(status)) = self.read_st_timed(&mut timeout) {
             let signaled = libc::WIFSIGNALED(status);
             let exit_code = libc::WEXITSTATUS(status);
+            if signaled {
+                self.last_signal = Some(libc::WTERMSIG(status));
+            }
             if signaled || (self.uses_asan && exit_code == MSAN_ERROR_CODE) {
                 StatusType::Crash
             } else {
@@ -1,4 +1,12 @@
-use crate::{branches::GlobalBranches, command::CommandOpt, depot::Depot, executor::Executor};
+use crate::{
+    branch_stats::BranchStats,
+    branches::GlobalBranches,
+    command::CommandOpt,
+    coordinate::{self, Coordinator},
+    depot::{hash_buf, Depot},
+    executor::Executor,
+    scheduler::Seed,
+};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex, RwLock,
@@ -17,10 +25,16 @@ use fastgen_common::config;
 use protobuf::Message;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::path::Path;
 //use crate::util::*;
+use crate::fuzz_stats::FuzzStats;
+use crate::provenance::Provenance;
 use crate::solution::*;
-use crate::z3solver::solve;
+use crate::source_map::SourceMap;
+use crate::timeline::Timeline;
+use crate::track_stats::{TrackRecord, TrackStats};
+use crate::z3solver::{solve, SolveStats};
 use blockingqueue::BlockingQueue;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use nix::unistd::close;
@@ -82,6 +96,11 @@ pub fn grading_loop(
     branch_fliplist: Arc<RwLock<HashSet<(u64, u64, u32, u64)>>>,
     forklock: Arc<Mutex<u32>>,
     solution_queue: BlockingQueue<Solution>,
+    stats: Arc<FuzzStats>,
+    branch_stats: Arc<BranchStats>,
+    source_map: Arc<SourceMap>,
+    timeline: Arc<Timeline>,
+    coordinator: Option<Arc<Coordinator>>,
 ) {
     let shmid = unsafe {
         libc::shmget(
@@ -98,8 +117,10 @@ pub fn grading_loop(
         shmid,
         true,
         forklock.clone(),
-        120
     );
+    executor.stats = Some(stats.clone());
+    executor.timeline = Some(timeline);
+    executor.source_map = Some(source_map.clone());
 
     //let branch_gencount = Arc::new(RwLock::new(HashMap::<(u64,u64,u32), u32>::new()));
     let t_start = time::Instant::now();
@@ -158,6 +179,10 @@ pub fn grading_loop(
         let mut target_cond = 0;
         //the hash for the constraint
         let mut cons_hash = 0;
+        // solutions that were not run, their bytes are in the depot already
+        let mut num_dups = 0;
+        // solutions that grew the input past the maximum length
+        let mut num_too_long = 0;
 
         let mut flipped_hashes: HashSet<u32> = HashSet::new();
         let mut notflipped_hashes: HashSet<u32> = HashSet::new();
@@ -165,6 +190,7 @@ pub fn grading_loop(
 
         while running.load(Ordering::Relaxed) {
             let sol = solution_queue.pop();
+            stats.num_dequeued.fetch_add(1, Ordering::Relaxed);
             //let id = unsafe { get_next_input_id() };
             //if id != std::u32::MAX {
             if let Some(mut buf) = depot.get_input_buf(sol.fid as usize) {
@@ -180,7 +206,18 @@ pub fn grading_loop(
                 predicate = sol.predicate;
                 fid = sol.fid;
                 let mut_buf = mutate(buf, &sol.sol, sol.field_index, sol.field_size);
+                if mut_buf.len() > executor.cmd.max_len {
+                    num_too_long += 1;
+                    continue;
+                }
+                // e.g. many solutions that end up with the same bytes
+                if depot.contains(hash_buf(&mut_buf)) {
+                    num_dups += 1;
+                    continue;
+                }
+                executor.provenance = Some(Provenance::solved(&sol));
                 let new_path = executor.run_sync_with_cond(&mut_buf, bid, sctx, order);
+                let counts = (flipped, reached, not_reached);
 
                 let direction_out = executor.get_cond();
                 if (direction_out == 0 && direction == 1) || (direction_out == 1 && direction == 0)
@@ -243,6 +280,18 @@ pub fn grading_loop(
                         notflipped_hashes.insert(cons_hash);
                     }
                 }
+                let (f, r, n) = (
+                    (flipped - counts.0) as usize,
+                    (reached - counts.1) as usize,
+                    (not_reached - counts.2) as usize,
+                );
+                stats.graded(f, r, n);
+                branch_stats.graded((sol.addr, sol.ctx, sol.order, sol.direction), f, r, n);
+                if let Some(ref coord) = coordinator {
+                    if f > 0 {
+                        coord.flip(&(sol.addr, sol.ctx, sol.order, sol.direction));
+                    }
+                }
                 if new_path.0 {
                     saved += 1;
                 }
@@ -274,8 +323,11 @@ pub fn grading_loop(
                 */
 
                 if new_path.0 {
-                    info!("grading input derived from on input {} by flipping branch@ {:#01x} ctx {:#01x} order {} direction {} bid {} sctx {}, it is a new input {}, saved as input #{}", 
-              fid, addr, ctx, order, direction, bid, sctx, new_path.0, new_path.1);
+                    // cached by the executor, which saved it
+                    let location = source_map.locate(sol.bid, sol.addr);
+                    info!("grading input derived from on input {} by flipping branch@ {:#01x} ctx {:#01x} order {} direction {} bid {} sctx {}, it is a new input {}, saved as input #{}{}", 
+              fid, addr, ctx, order, direction, bid, sctx, new_path.0, new_path.1,
+              location.map(|loc| format!(", branch at {}", loc)).unwrap_or_default());
                     let mut count = 1;
                     if addr != 0
                         && branch_gencount
@@ -303,7 +355,12 @@ pub fn grading_loop(
             if grade_count % 1000 == 0 {
                 let used_t1 = t_start.elapsed().as_secs() as u32;
                 if used_t1 != 0 {
-                    warn!("Grading throughput is {}", grade_count / used_t1);
+                    warn!(
+                        "Grading throughput is {}, skipped {} duplicates and {} too long",
+                        grade_count / used_t1,
+                        num_dups,
+                        num_too_long
+                    );
                 }
             }
         }
@@ -318,8 +375,11 @@ pub fn constraint_solver(
     branch_gencount: Arc<RwLock<HashMap<(u64, u64, u32, u64), u32>>>,
     branch_fliplist: Arc<RwLock<HashSet<(u64, u64, u32, u64)>>>,
     branch_hitcount: Arc<RwLock<HashMap<(u64, u64, u32, u64), u32>>>,
-    solver_timeout: u64
-) {
+    solver_timeout: u64,
+    fuzz_stats: Arc<FuzzStats>,
+    branch_stats: Arc<BranchStats>,
+    coordinator: Option<Arc<Coordinator>>,
+) -> SolveStats {
     unsafe {
         solve(
             shmid,
@@ -329,9 +389,12 @@ pub fn constraint_solver(
             &branch_gencount,
             &branch_fliplist,
             &branch_hitcount,
-            solver_timeout
-        );
-    };
+            solver_timeout,
+            &fuzz_stats,
+            &branch_stats,
+            coordinator.as_deref(),
+        )
+    }
 }
 
 //fuzz loop with parsing in C++
@@ -342,18 +405,18 @@ pub fn fuzz_loop(
     global_branches: Arc<GlobalBranches>,
     branch_gencount: Arc<RwLock<HashMap<(u64, u64, u32, u64), u32>>>,
     branch_fliplist: Arc<RwLock<HashSet<(u64, u64, u32, u64)>>>,
-    restart: bool,
+    branch_hitcount: Arc<RwLock<HashMap<(u64, u64, u32, u64), u32>>>,
     forklock: Arc<Mutex<u32>>,
     bq: BlockingQueue<Solution>,
-    executor_timeout: u64,
-    solver_timeout: u64
+    track_stats: Arc<TrackStats>,
+    solver_timeout: u64,
+    stats: Arc<FuzzStats>,
+    branch_stats: Arc<BranchStats>,
+    timeline: Arc<Timeline>,
+    coordinator: Option<Arc<Coordinator>>,
 ) {
     let executor_id = cmd_opt.id;
 
-    if restart {
-        depot.load_cached_next_id();
-    }
-
     let shmid = unsafe {
         libc::shmget(
             libc::IPC_PRIVATE,
@@ -374,24 +437,61 @@ pub fn fuzz_loop(
         shmid,
         true, //not grading
         forklock.clone(),
-        executor_timeout
     );
+    executor.stats = Some(stats.clone());
+    executor.timeline = Some(timeline);
 
-    let branch_hitcount = Arc::new(RwLock::new(HashMap::<(u64, u64, u32, u64), u32>::new()));
+    // seeds claimed by another instance, with when to claim them again; they
+    // stay in flight for the checkpoint
+    let mut deferred: VecDeque<(Seed, time::Instant)> = VecDeque::new();
 
     while running.load(Ordering::Relaxed) {
-        match depot.get_next_input() {
-            Some(id) => {
+        let next = match deferred.front() {
+            Some(&(_, retry_at)) if retry_at <= time::Instant::now() => {
+                deferred.pop_front().map(|(seed, _)| seed)
+            }
+            _ => depot.get_next_input(),
+        };
+        match next {
+            Some(seed) => {
+                let id = seed.id;
                 let t_start = time::Instant::now();
                 info!("{} running {}", tid, id);
 
                 if let Some(buf) = depot.get_input_buf(id as usize) {
-                    let (mut child, read_end) = executor.track(id as usize, &buf);
+                    // queued before resuming with a smaller --max-len
+                    if buf.len() > executor.cmd.max_len {
+                        warn!("Skip tracking {}, it is longer than {}", id, executor.cmd.max_len);
+                        depot.done(id);
+                        continue;
+                    }
+                    // another instance on the sync directory tracks it
+                    let seed_hash = coordinate::seed_hash(&buf);
+                    if let Some(ref coord) = coordinator {
+                        if !coord.claim_seed(seed_hash) {
+                            if coord.seed_done(seed_hash) {
+                                info!("Skip tracking {}, another instance tracked it", id);
+                                depot.done(id);
+                            } else {
+                                info!("Defer tracking {}, it is claimed by another instance", id);
+                                let retry = time::Duration::from_secs(config::COORD_CLAIM_RETRY);
+                                deferred.push_back((seed, time::Instant::now() + retry));
+                            }
+                            continue;
+                        }
+                    }
+                    let (child, read_end) = executor.track(id as usize, &buf);
+                    // the union table only has labels for the bytes of the input
+                    let tainted_size = buf.len();
 
                     let gbranch_hitcount = branch_hitcount.clone();
                     let gbranch_fliplist = branch_fliplist.clone();
                     let gbranch_gencount = branch_gencount.clone();
                     let solution_queue = bq.clone();
+                    let fuzz_stats = stats.clone();
+                    let gbranch_stats = branch_stats.clone();
+                    let gcoordinator = coordinator.clone();
+                    stats.start_tracking(tid, id);
                     let handle = thread::Builder::new()
                         .stack_size(64 * 1024 * 1024)
                         .spawn(move || {
@@ -399,18 +499,25 @@ pub fn fuzz_loop(
                                 shmid,
                                 read_end,
                                 solution_queue,
-                                buf.len(),
+                                tainted_size,
                                 gbranch_gencount,
                                 gbranch_fliplist,
                                 gbranch_hitcount,
-                                solver_timeout
-                            );
+                                solver_timeout,
+                                fuzz_stats,
+                                gbranch_stats,
+                                gcoordinator,
+                            )
                         })
                         .unwrap();
 
-                    if handle.join().is_err() {
-                        error!("Error happened in listening thread!");
-                    }
+                    let solve_stats = match handle.join() {
+                        Ok(solve_stats) => solve_stats,
+                        Err(_) => {
+                            error!("Error happened in listening thread!");
+                            SolveStats::default()
+                        }
+                    };
 
                     //      constraint_solver(shmid, read_end);
                     info!("Done solving {}", id);
@@ -421,24 +528,49 @@ pub fn fuzz_loop(
                     //     .map_err(|err| debug!("close read end {:?}", err))
                     //     .ok();
 
-                    //let timeout = time::Duration::from_secs(90);
-                    //child.wait_timeout(timeout);
-                    match child.try_wait() {
-                        Ok(Some(status)) => println!("exited with: {}", status),
-                        Ok(None) => {
-                            println!("status not ready yet, let's really wait");
-                            child.kill();
-                            let res = child.wait();
-                            println!("result: {:?}", res);
+                    let track_exit = executor.finish_track(child);
+                    stats.done_tracking(tid);
+                    let mut record = TrackRecord {
+                        id,
+                        exit: track_exit,
+                        time: t_start.elapsed(),
+                        solve: solve_stats,
+                        attempts: 1,
+                        failures: 0,
+                    };
+                    if track_exit.is_failure() {
+                        warn!("Tracking {} failed with {}", id, track_exit);
+                        record.failures += 1;
+                        // find out whether it fails every time
+                        if let Some(buf) = depot.get_input_buf(id) {
+                            for _ in 0..config::TRACK_RETRIES {
+                                record.attempts += 1;
+                                if executor.track_drain(id, &buf).is_failure() {
+                                    record.failures += 1;
+                                }
+                            }
+                        }
+                        if record.always_fails() {
+                            warn!("Tracking {} always fails, saved to track failures", id);
+                            depot.save_track_failure(id);
                         }
-                        Err(e) => println!("error attempting to wait: {}", e),
+                    } else {
+                        info!("Tracking {} exited with {}", id, track_exit);
+                    }
+                    track_stats.record(&record);
+                    trace!("track time {}", record.time.as_micros());
+                    if solve_stats.time_capped {
+                        warn!("Solving {} hit the {}s cap", id, config::MAX_SOLVE_TIME);
+                        // our claim stays, it is finished when tracked again
+                        if depot.requeue(seed) {
+                            continue;
+                        }
+                    }
+                    if let Some(ref coord) = coordinator {
+                        coord.finish_seed(seed_hash);
                     }
-
-                    let used_t1 = t_start.elapsed();
-                    let used_us1 =
-                        (used_t1.as_secs() as u32 * 1000_000) + used_t1.subsec_nanos() / 1_000;
-                    trace!("track time {}", used_us1);
                 }
+                depot.done(id);
             }
             None => {
                 if config::RUNAFL {
@@ -461,6 +593,7 @@ mod tests {
     use crate::branches;
     use crate::command;
     use crate::depot;
+    use crate::scheduler::SchedulePolicy;
     use std::fs;
     use std::path::PathBuf;
 
@@ -481,11 +614,20 @@ mod tests {
         let args = vec!["./size.fast".to_string(), "@@".to_string()];
         fs::create_dir(&angora_out_dir).expect("Output directory has existed!");
 
-        let cmd_opt = command::CommandOpt::new("./size.track", args, &angora_out_dir, 200, 1);
+        let cmd_opt = command::CommandOpt::new(
+            "./size.track",
+            args,
+            &angora_out_dir,
+            200,
+            1000,
+            120000,
+            None,
+            config::MAX_INPUT_LEN,
+        );
 
-        let depot = Arc::new(depot::Depot::new(seeds_dir, &angora_out_dir));
+        let depot = Arc::new(depot::Depot::new(seeds_dir, &angora_out_dir, SchedulePolicy::Fifo));
 
-        let global_branches = Arc::new(branches::GlobalBranches::new());
+        let global_branches = Arc::new(branches::GlobalBranches::new(config::BRANCHES_SIZE));
 
         let mut executor = Executor::new(
             cmd_opt.specify(1),
@@ -1,5 +1,5 @@
 use chrono::prelude::Local;
-use fastgen_common::defs;
+use fastgen_common::{coverage_mode::CoverageMode, defs};
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -12,8 +12,13 @@ use std::{
 
 use crate::cpp_interface::*;
 use crate::fuzz_loop;
+use crate::scheduler::SchedulePolicy;
 use crate::solution::*;
-use crate::{branches, check_dep, command, depot, executor, sync};
+use crate::{
+    bind_cpu, branch_stats, branches, calibrate, cgroup, check_dep, checkpoint, command, depot, executor,
+    fuzz_stats, source_map, sync, timeline, track_stats, tui,
+};
+use crate::coordinate::Coordinator;
 use blockingqueue::BlockingQueue;
 use ctrlc;
 use fastgen_common::config;
@@ -31,30 +36,134 @@ pub fn fuzz_main(
     _num_jobs: usize,
     _num_graders: usize,
     mem_limit: u64,
-    time_limit: u64,
+    time_limit: Option<u64>,
     sync_afl: bool,
-    executor_timeout: u64,
-    solver_timeout: u64
+    executor_timeout: Option<u64>,
+    solver_timeout: u64,
+    cgroup_dir: Option<&str>,
+    schedule: SchedulePolicy,
+    max_len: usize,
+    truncate_seeds: bool,
+    tui: bool,
+    cond_locs: Option<&str>,
+    map_size: Option<usize>,
+    coverage_mode: Option<CoverageMode>,
+    watch_dirs: Vec<&str>,
+    instance: &str,
+    coordinate: bool,
+    dwarf_locs: bool,
 ) {
     pretty_env_logger::init();
 
-    let (seeds_dir, angora_out_dir) = initialize_directories(in_dir, out_dir, sync_afl);
+    if let Some(size) = map_size {
+        if !size.is_power_of_two()
+            || size < 1 << config::MAP_SIZE_POW2_MIN
+            || size > 1 << config::MAP_SIZE_POW2_MAX
+        {
+            panic!("The map size should be a power of two from 2^16 to 2^24.");
+        }
+    }
 
-    let command_option =
-        command::CommandOpt::new(track_target, pargs, &angora_out_dir, mem_limit, time_limit);
-    info!("{:?}", command_option);
+    if instance.is_empty()
+        || !instance.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        panic!("The instance name should only have letters, digits, '_' and '-'.");
+    }
+    if coordinate && !sync_afl {
+        panic!("--coordinate needs -S, the instances coordinate on the sync directory.");
+    }
+
+    let (seeds_dir, angora_out_dir, resume) =
+        initialize_directories(in_dir, out_dir, sync_afl, instance);
+
+    let cgroup_root = cgroup_dir.map(|dir| cgroup::create_root(Path::new(dir)));
+
+    let mut command_option = command::CommandOpt::new(
+        track_target,
+        pargs,
+        &angora_out_dir,
+        mem_limit,
+        time_limit.unwrap_or(config::TIME_LIMIT),
+        executor_timeout.unwrap_or(config::TIME_LIMIT_TRACK),
+        cgroup_root.clone(),
+        max_len,
+    );
+    command_option.coverage_mode = resolve_coverage_mode(&angora_out_dir, coverage_mode);
+    let map_size = resolve_map_size(&angora_out_dir, map_size);
 
-    let restart = in_dir == "-";
     check_dep::check_dep(in_dir, out_dir, &command_option);
 
-    let depot = Arc::new(depot::Depot::new(seeds_dir, &angora_out_dir));
+    let depot = Arc::new(depot::Depot::new(seeds_dir, &angora_out_dir, schedule));
     info!("{:?}", depot.dirs);
 
-    let global_branches = Arc::new(branches::GlobalBranches::new());
+    let global_branches = Arc::new(branches::GlobalBranches::new(map_size));
+    let forklock = Arc::new(Mutex::new(0));
+
     let branch_gencount = Arc::new(RwLock::new(HashMap::<(u64, u64, u32, u64), u32>::new()));
     let branch_fliplist = Arc::new(RwLock::new(HashSet::<(u64, u64, u32, u64)>::new()));
+    // each tracker keeps its own hit counts
+    let branch_hitcounts: Vec<_> = (0.._num_jobs)
+        .map(|_| Arc::new(RwLock::new(HashMap::<(u64, u64, u32, u64), u32>::new())))
+        .collect();
+    let branch_stats = Arc::new(branch_stats::BranchStats::new());
+    let timeline = Arc::new(timeline::Timeline::new(&angora_out_dir));
+    let state = checkpoint::CampaignState {
+        global_branches: global_branches.clone(),
+        depot: depot.clone(),
+        branch_gencount: branch_gencount.clone(),
+        branch_fliplist: branch_fliplist.clone(),
+        branch_hitcounts: branch_hitcounts.clone(),
+        branch_stats: branch_stats.clone(),
+        timeline: timeline.clone(),
+    };
+
+    //  unsafe { init_core(config::SAVING_WHOLE, config::USE_CODECACHE); }
+    unsafe {
+        init_core();
+    }
+
+    let (calibrated, track_calibrated) = if resume {
+        let timeouts = state
+            .load(&angora_out_dir)
+            .expect("Could not load checkpoint.");
+        info!(
+            "Resumed with {} inputs, {} queued",
+            depot.get_num_inputs(),
+            depot.get_num_queued()
+        );
+        timeouts
+    } else if let (Some(t), Some(et)) = (time_limit, executor_timeout) {
+        info!("Both timeouts are given, skip calibration");
+        (t, et)
+    } else {
+        // this also finds the unstable edges of the seeds
+        calibrate::calibrate_timeouts(
+            &command_option,
+            global_branches.clone(),
+            depot.clone(),
+            forklock.clone(),
+            &depot.dirs.seeds_dir,
+        )
+    };
+    command_option.time_limit = time_limit.unwrap_or(calibrated);
+    command_option.track_time_limit = executor_timeout.unwrap_or(track_calibrated);
+    info!("{:?}", command_option);
+
+    let track_stats = Arc::new(track_stats::TrackStats::new(&angora_out_dir));
+    let fuzz_stats = Arc::new(fuzz_stats::FuzzStats::new(timeline.clone()));
+    // addr2line runs for each new addr, with PIE the addrs change every run
+    let track_bin = if dwarf_locs {
+        Some(command_option.track.0.as_str())
+    } else {
+        None
+    };
+    let source_map = Arc::new(source_map::SourceMap::new(cond_locs, track_bin));
+    let coordinator = if coordinate {
+        Some(Arc::new(Coordinator::open(Path::new(out_dir), instance)))
+    } else {
+        None
+    };
     let running = Arc::new(AtomicBool::new(true));
-    let forklock = Arc::new(Mutex::new(0));
     set_sigint_handler(running.clone());
 
     let mut executor = executor::Executor::new(
@@ -62,12 +171,20 @@ pub fn fuzz_main(
         global_branches.clone(),
         depot.clone(),
         0,
-        false, //not grading
+        true, // runs the imported inputs
         forklock.clone(),
-        executor_timeout
     );
+    executor.stats = Some(fuzz_stats.clone());
+    executor.timeline = Some(timeline.clone());
 
-    sync::sync_depot(&mut executor, running.clone(), &depot.dirs.seeds_dir);
+    if !resume {
+        sync::sync_depot(
+            &mut executor,
+            running.clone(),
+            &depot.dirs.seeds_dir,
+            truncate_seeds,
+        );
+    }
 
     if depot.empty() {
         error!(
@@ -76,15 +193,13 @@ pub fn fuzz_main(
         );
     }
 
-    //  unsafe { init_core(config::SAVING_WHOLE, config::USE_CODECACHE); }
-    unsafe {
-        init_core();
-    }
     let mut handlers = vec![];
 
     assert!(_num_graders == _num_jobs);
     let first_grader = 2;
     let first_executor = first_grader + _num_graders;
+    // one core for each grader and one for each tracker/solver pair
+    let free_cpus = bind_cpu::find_free_cpus(_num_graders + _num_jobs);
 
     for g in 0.._num_jobs {
         let bq = BlockingQueue::new();
@@ -97,9 +212,18 @@ pub fn fuzz_main(
         let blist = branch_fliplist.clone();
         let fk = forklock.clone();
         let bqc = bq.clone();
+        let fs = fuzz_stats.clone();
+        let bs = branch_stats.clone();
+        let sm = source_map.clone();
+        let tl = timeline.clone();
+        let co = coordinator.clone();
+        let cpu = bind_cpu::pick_cpu(&free_cpus, 2 * g);
         let handle = thread::spawn(move || {
+            if let Some(cid) = cpu {
+                bind_cpu::bind_thread_to_cpu_core(cid);
+            }
             //fuzz_loop::branch_checking(r, cmd, d, b, bg, bs);
-            fuzz_loop::grading_loop(r, cmd, d, b, bg, blist, fk, bqc);
+            fuzz_loop::grading_loop(r, cmd, d, b, bg, blist, fk, bqc, fs, bs, sm, tl, co);
         });
         handlers.push(handle);
 
@@ -110,12 +234,38 @@ pub fn fuzz_main(
             let cmd = command_option.specify(first_executor + g);
             let bg = branch_gencount.clone();
             let blist = branch_fliplist.clone();
+            let bh = branch_hitcounts[g].clone();
             let fk = forklock.clone();
             let bqc = bq.clone();
+            let ts = track_stats.clone();
+            let fs = fuzz_stats.clone();
+            let bs = branch_stats.clone();
+            let tl = timeline.clone();
+            let co = coordinator.clone();
+            let cpu = bind_cpu::pick_cpu(&free_cpus, 2 * g + 1);
             let handle = thread::Builder::new()
                 .stack_size(64 * 1024 * 1024)
                 .spawn(move || {
-                    fuzz_loop::fuzz_loop(r, cmd, d, b, bg, blist, restart, fk, bqc, executor_timeout, solver_timeout);
+                    if let Some(cid) = cpu {
+                        bind_cpu::bind_thread_to_cpu_core(cid);
+                    }
+                    fuzz_loop::fuzz_loop(
+                        r,
+                        cmd,
+                        d,
+                        b,
+                        bg,
+                        blist,
+                        bh,
+                        fk,
+                        bqc,
+                        ts,
+                        solver_timeout,
+                        fs,
+                        bs,
+                        tl,
+                        co,
+                    );
                 })
                 .unwrap();
             handlers.push(handle);
@@ -123,23 +273,92 @@ pub fn fuzz_main(
 
     }
 
-    main_thread_sync(out_dir, sync_afl, running.clone(), &mut executor);
+    {
+        let r = running.clone();
+        let mut writer = fuzz_stats::StatsWriter::new(
+            &angora_out_dir,
+            &command_option,
+            fuzz_stats.clone(),
+            depot.clone(),
+            global_branches.clone(),
+            track_stats.clone(),
+        );
+        let handle = thread::spawn(move || {
+            while r.load(Ordering::SeqCst) {
+                writer.write();
+                thread::sleep(time::Duration::from_secs(config::STATS_INTERVAL));
+            }
+            writer.write();
+        });
+        handlers.push(handle);
+    }
+
+    if tui {
+        let r = running.clone();
+        let screen = tui::StatusScreen::new(
+            &command_option.main.0,
+            fuzz_stats.clone(),
+            depot.clone(),
+            global_branches.clone(),
+            track_stats.clone(),
+        );
+        let handle = thread::spawn(move || {
+            let mut ticks = 0;
+            while r.load(Ordering::SeqCst) {
+                thread::sleep(time::Duration::from_secs(1));
+                ticks += 1;
+                if ticks % screen.interval() == 0 {
+                    screen.draw();
+                }
+            }
+        });
+        handlers.push(handle);
+    }
+
+    main_thread_sync(
+        out_dir,
+        &angora_out_dir,
+        sync_afl,
+        instance,
+        &watch_dirs,
+        running.clone(),
+        &mut executor,
+        &state,
+    );
 
     for handle in handlers {
         if handle.join().is_err() {
             error!("Error happened in fuzzing thread!");
         }
     }
+    state.save(&angora_out_dir, &command_option);
+
+    // the executors, and their cgroups, are gone by now
+    drop(executor);
+    if let Some(root) = cgroup_root {
+        cgroup::remove_root(&root);
+    }
 }
 
-fn initialize_directories(in_dir: &str, out_dir: &str, sync_afl: bool) -> (PathBuf, PathBuf) {
+// Returns the seeds dir, the output dir, and whether to resume from a checkpoint.
+fn initialize_directories(
+    in_dir: &str,
+    out_dir: &str,
+    sync_afl: bool,
+    instance: &str,
+) -> (PathBuf, PathBuf, bool) {
     let angora_out_dir = if sync_afl {
-        gen_path_afl(out_dir)
+        gen_path_afl(out_dir, instance)
     } else {
         PathBuf::from(out_dir)
     };
 
     let restart = in_dir == "-";
+    // resume in place, otherwise start over with the old queue as seeds
+    if restart && checkpoint::checkpoint_path(&angora_out_dir).is_file() {
+        let seeds_dir = angora_out_dir.join(defs::INPUTS_DIR);
+        return (seeds_dir, angora_out_dir, true);
+    }
     if !restart {
         fs::create_dir(&angora_out_dir).expect("Output directory has existed!");
     }
@@ -157,16 +376,46 @@ fn initialize_directories(in_dir: &str, out_dir: &str, sync_afl: bool) -> (PathB
         PathBuf::from(in_dir)
     };
 
-    (seeds_dir, angora_out_dir)
+    (seeds_dir, angora_out_dir, false)
 }
 
-fn gen_path_afl(out_dir: &str) -> PathBuf {
+// A resumed campaign keeps the mode it was started with.
+fn resolve_coverage_mode(out_dir: &Path, mode: Option<CoverageMode>) -> CoverageMode {
+    let recorded = CoverageMode::load(out_dir).expect("Could not read the coverage mode.");
+    let mode = match (recorded, mode) {
+        (Some(recorded), Some(mode)) if recorded != mode => {
+            panic!("The output directory was fuzzed with --coverage {}.", recorded)
+        }
+        (Some(recorded), _) => recorded,
+        (None, mode) => mode.unwrap_or_default(),
+    };
+    mode.save(out_dir).expect("Could not save the coverage mode.");
+    info!("Coverage mode: {}", mode);
+    mode
+}
+
+// Like the coverage mode, cmin reads it back.
+fn resolve_map_size(out_dir: &Path, size: Option<usize>) -> usize {
+    let recorded = branches::load_map_size(out_dir).expect("Could not read the map size.");
+    let size = match (recorded, size) {
+        (Some(recorded), Some(size)) if recorded != size => {
+            panic!("The output directory was fuzzed with --map-size {}.", recorded)
+        }
+        (Some(recorded), _) => recorded,
+        (None, size) => size.unwrap_or(config::BRANCHES_SIZE),
+    };
+    branches::save_map_size(out_dir, size).expect("Could not save the map size.");
+    info!("Map size: {}", size);
+    size
+}
+
+fn gen_path_afl(out_dir: &str, instance: &str) -> PathBuf {
     let base_path = PathBuf::from(out_dir);
     let create_dir_result = fs::create_dir(&base_path);
     if create_dir_result.is_err() {
         warn!("dir has existed. {:?}", base_path);
     }
-    base_path.join(defs::ANGORA_DIR_NAME)
+    base_path.join(instance)
 }
 
 fn set_sigint_handler(r: Arc<AtomicBool>) {
@@ -179,22 +428,58 @@ fn set_sigint_handler(r: Arc<AtomicBool>) {
 
 fn main_thread_sync(
     out_dir: &str,
+    angora_out_dir: &Path,
     sync_afl: bool,
+    instance: &str,
+    watch_dirs: &[&str],
     running: Arc<AtomicBool>,
     executor: &mut executor::Executor,
+    state: &checkpoint::CampaignState,
 ) {
+    let global_branches = &state.global_branches;
     let sync_dir = Path::new(out_dir);
-    let mut synced_ids = HashMap::new();
+    let mut synced_ids = sync::SyncIds::new(angora_out_dir);
     if sync_afl {
-        sync::sync_afl(executor, running.clone(), sync_dir, &mut synced_ids);
+        sync::sync_afl(executor, running.clone(), sync_dir, instance, &mut synced_ids);
     }
+    let watcher = sync::Watcher::new(watch_dirs);
+    watcher.scan(executor, running.clone(), &mut synced_ids);
     let mut sync_counter = 1;
+    let mut stat_counter = 0;
     while running.load(Ordering::SeqCst) {
-        thread::sleep(time::Duration::from_secs(5));
+        // a tick of 5 seconds, new files in the watched directories wake us up
+        let tick = time::Instant::now() + time::Duration::from_secs(5);
+        while let Some(left) = tick.checked_duration_since(time::Instant::now()) {
+            if !running.load(Ordering::SeqCst) || left.as_millis() == 0 {
+                break;
+            }
+            watcher.wait(executor, running.clone(), left, &mut synced_ids);
+        }
         sync_counter -= 1;
         if sync_afl && sync_counter <= 0 {
-            sync::sync_afl(executor, running.clone(), sync_dir, &mut synced_ids);
+            sync::sync_afl(executor, running.clone(), sync_dir, instance, &mut synced_ids);
             sync_counter = 12;
         }
+        stat_counter += 1;
+        if stat_counter % (config::CHECKPOINT_INTERVAL / 5) == 0 {
+            state.save(angora_out_dir, &executor.cmd);
+        }
+        // about once a minute
+        if stat_counter % 12 == 0 {
+            info!(
+                "density {}%, stability {}% ({} unstable edges)",
+                global_branches.get_density(),
+                global_branches.get_stability(),
+                global_branches.get_num_unstable()
+            );
+            let (hits, misses, size) = state.depot.get_cache_stats();
+            info!(
+                "input cache: {} hits, {} misses ({}% hit rate), {} KB",
+                hits,
+                misses,
+                hits * 100 / std::cmp::max(hits + misses, 1),
+                size >> 10
+            );
+        }
     }
 }
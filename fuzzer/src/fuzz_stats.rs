@@ -0,0 +1,371 @@
+// fuzzer_stats and plot_data in AFL++'s format, so that afl-whatsup, afl-plot
+// and the dashboards built for AFL work on fastgen's output directory too.
+// The fastgen fields come after AFL's, which tools that parse by name or by
+// column skip.
+
+use crate::{
+    branches::GlobalBranches, command::CommandOpt, depot::Depot, status_type::StatusType,
+    timeline::Timeline, track_stats::TrackStats,
+};
+use fastgen_common::defs;
+use std::{
+    collections::HashMap,
+    fmt::Write as FmtWrite,
+    fs::{self, File, OpenOptions},
+    io::prelude::*,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time,
+};
+
+static PLOT_DATA_HEADER: &str = "# relative_time, cycles_done, cur_item, corpus_count, \
+     pending_total, pending_favs, map_size, saved_crashes, saved_hangs, max_depth, \
+     execs_per_sec, total_execs, edges_found, graded, flipped, reached, not_reached";
+
+fn unix_time() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Counters shared by the executors and the graders.
+pub struct FuzzStats {
+    // of this run, the counters start at 0 with it
+    start: time::Instant,
+    // the run time of the campaign, it goes on when resuming
+    timeline: Arc<Timeline>,
+    pub num_execs: AtomicUsize,
+    // imported inputs that were queued
+    pub num_imported: AtomicUsize,
+    // solutions that were run
+    pub num_graded: AtomicUsize,
+    pub num_flipped: AtomicUsize,
+    pub num_reached: AtomicUsize,
+    pub num_not_reached: AtomicUsize,
+    pub solve_time_us: AtomicU64,
+    // solutions pushed by the solvers and popped by the graders
+    pub num_solutions: AtomicUsize,
+    pub num_dequeued: AtomicUsize,
+    // unix time, 0 if never
+    last_find: AtomicU64,
+    last_crash: AtomicU64,
+    last_hang: AtomicU64,
+    // tracking thread -> (seed id, when it started)
+    tracking: Mutex<HashMap<usize, (usize, time::Instant)>>,
+}
+
+impl FuzzStats {
+    pub fn new(timeline: Arc<Timeline>) -> Self {
+        Self {
+            start: time::Instant::now(),
+            timeline,
+            num_execs: AtomicUsize::new(0),
+            num_imported: AtomicUsize::new(0),
+            num_graded: AtomicUsize::new(0),
+            num_flipped: AtomicUsize::new(0),
+            num_reached: AtomicUsize::new(0),
+            num_not_reached: AtomicUsize::new(0),
+            solve_time_us: AtomicU64::new(0),
+            num_solutions: AtomicUsize::new(0),
+            num_dequeued: AtomicUsize::new(0),
+            last_find: AtomicU64::new(0),
+            last_crash: AtomicU64::new(0),
+            last_hang: AtomicU64::new(0),
+            tracking: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn start_tracking(&self, tid: usize, id: usize) {
+        self.tracking
+            .lock()
+            .unwrap()
+            .insert(tid, (id, time::Instant::now()));
+    }
+
+    pub fn done_tracking(&self, tid: usize) {
+        self.tracking.lock().unwrap().remove(&tid);
+    }
+
+    // An input was saved to the depot.
+    pub fn saved(&self, status: StatusType) {
+        let last = match status {
+            StatusType::Normal => &self.last_find,
+            StatusType::Crash | StatusType::Oom => &self.last_crash,
+            StatusType::Timeout => &self.last_hang,
+            _ => return,
+        };
+        last.store(unix_time(), Ordering::Relaxed);
+    }
+
+    // A branch was solved, once for all the solutions it gave.
+    pub fn solved(&self, solve_time: time::Duration) {
+        self.solve_time_us
+            .fetch_add(solve_time.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    // A solution was run, with how many branches it flipped, reached or missed.
+    pub fn graded(&self, flipped: usize, reached: usize, not_reached: usize) {
+        self.num_graded.fetch_add(1, Ordering::Relaxed);
+        self.num_flipped.fetch_add(flipped, Ordering::Relaxed);
+        self.num_reached.fetch_add(reached, Ordering::Relaxed);
+        self.num_not_reached.fetch_add(not_reached, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(
+        &self,
+        depot: &Depot,
+        global_branches: &GlobalBranches,
+        track_stats: &TrackStats,
+    ) -> Snapshot {
+        let mut tracking: Vec<(usize, usize, time::Duration)> = self
+            .tracking
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&tid, &(id, t_start))| (tid, id, t_start.elapsed()))
+            .collect();
+        tracking.sort();
+        let (cache_hits, cache_misses, cache_size) = depot.get_cache_stats();
+        let now = unix_time();
+        let run_time = self.timeline.run_time_ms() / 1000;
+        Snapshot {
+            start_time: now.saturating_sub(run_time),
+            now,
+            run_time,
+            this_run_time: self.start.elapsed().as_secs(),
+            execs: self.num_execs.load(Ordering::Relaxed),
+            corpus_count: depot.get_num_inputs(),
+            imported: self.num_imported.load(Ordering::Relaxed),
+            pending: depot.get_num_queued(),
+            // like AFL, OOMs are crashes
+            crashes: depot.num_crashes.load(Ordering::Relaxed)
+                + depot.num_ooms.load(Ordering::Relaxed),
+            hangs: depot.num_hangs.load(Ordering::Relaxed),
+            edges: global_branches.get_num_edges(),
+            density: global_branches.get_density(),
+            stability: global_branches.get_stability(),
+            tracked: track_stats.num_tracked.load(Ordering::Relaxed),
+            solutions: self.num_solutions.load(Ordering::Relaxed),
+            dequeued: self.num_dequeued.load(Ordering::Relaxed),
+            graded: self.num_graded.load(Ordering::Relaxed),
+            flipped: self.num_flipped.load(Ordering::Relaxed),
+            reached: self.num_reached.load(Ordering::Relaxed),
+            not_reached: self.num_not_reached.load(Ordering::Relaxed),
+            solve_time_us: self.solve_time_us.load(Ordering::Relaxed),
+            last_find: self.last_find.load(Ordering::Relaxed),
+            last_crash: self.last_crash.load(Ordering::Relaxed),
+            last_hang: self.last_hang.load(Ordering::Relaxed),
+            cache_hits,
+            cache_misses,
+            cache_size,
+            tracking,
+        }
+    }
+}
+
+// The counters at one point in time, with what the depot and the bitmap say.
+pub struct Snapshot {
+    pub start_time: u64,
+    pub now: u64,
+    // s, of the campaign and of this run, which the counters are of
+    pub run_time: u64,
+    pub this_run_time: u64,
+    pub execs: usize,
+    pub corpus_count: usize,
+    pub imported: usize,
+    pub pending: usize,
+    pub crashes: usize,
+    pub hangs: usize,
+    pub edges: usize,
+    pub density: f32,
+    pub stability: f32,
+    pub tracked: usize,
+    pub solutions: usize,
+    pub dequeued: usize,
+    pub graded: usize,
+    pub flipped: usize,
+    pub reached: usize,
+    pub not_reached: usize,
+    pub solve_time_us: u64,
+    pub last_find: u64,
+    pub last_crash: u64,
+    pub last_hang: u64,
+    // the input cache of the depot, size in bytes
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+    pub cache_size: usize,
+    // (tracking thread, seed id, time spent on it so far)
+    pub tracking: Vec<(usize, usize, time::Duration)>,
+}
+
+impl Snapshot {
+    fn per_sec(&self, n: usize) -> f64 {
+        n as f64 / std::cmp::max(self.this_run_time, 1) as f64
+    }
+
+    pub fn execs_per_sec(&self) -> f64 {
+        self.per_sec(self.execs)
+    }
+
+    pub fn graded_per_sec(&self) -> f64 {
+        self.per_sec(self.graded)
+    }
+
+    pub fn corpus_found(&self) -> usize {
+        self.corpus_count.saturating_sub(self.imported)
+    }
+
+    // solutions waiting for a grader
+    pub fn queued(&self) -> usize {
+        self.solutions.saturating_sub(self.dequeued)
+    }
+}
+
+// AFL pads the keys to 18 columns.
+fn format_stats(fields: &[(&str, String)]) -> String {
+    let mut s = String::new();
+    for (key, val) in fields {
+        let _ = writeln!(s, "{:<18}: {}", key, val);
+    }
+    s
+}
+
+pub struct StatsWriter {
+    out_dir: PathBuf,
+    command_line: String,
+    plot_data: File,
+    stats: Arc<FuzzStats>,
+    depot: Arc<Depot>,
+    global_branches: Arc<GlobalBranches>,
+    track_stats: Arc<TrackStats>,
+}
+
+impl StatsWriter {
+    pub fn new(
+        out_dir: &Path,
+        cmd: &CommandOpt,
+        stats: Arc<FuzzStats>,
+        depot: Arc<Depot>,
+        global_branches: Arc<GlobalBranches>,
+        track_stats: Arc<TrackStats>,
+    ) -> Self {
+        let path = out_dir.join(defs::PLOT_DATA_FILE);
+        let mut plot_data = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .expect("Could not create plot data file.");
+        if plot_data.metadata().map(|m| m.len() == 0).unwrap_or(false) {
+            writeln!(plot_data, "{}", PLOT_DATA_HEADER)
+                .expect("Could not write plot data file.");
+        }
+        let mut command_line = cmd.main.0.clone();
+        for arg in &cmd.main.1 {
+            command_line.push(' ');
+            command_line.push_str(arg);
+        }
+        Self {
+            out_dir: out_dir.to_path_buf(),
+            command_line,
+            plot_data,
+            stats,
+            depot,
+            global_branches,
+            track_stats,
+        }
+    }
+
+    pub fn write(&mut self) {
+        let snap = self
+            .stats
+            .snapshot(&self.depot, &self.global_branches, &self.track_stats);
+        let fields = [
+            ("start_time", snap.start_time.to_string()),
+            ("last_update", snap.now.to_string()),
+            ("run_time", snap.run_time.to_string()),
+            ("fuzzer_pid", std::process::id().to_string()),
+            ("cycles_done", "0".to_string()),
+            ("cycles_wo_finds", "0".to_string()),
+            ("execs_done", snap.execs.to_string()),
+            ("execs_per_sec", format!("{:.2}", snap.execs_per_sec())),
+            ("corpus_count", snap.corpus_count.to_string()),
+            ("corpus_favored", "0".to_string()),
+            ("corpus_found", snap.corpus_found().to_string()),
+            ("corpus_imported", snap.imported.to_string()),
+            ("cur_item", "0".to_string()),
+            ("pending_favs", "0".to_string()),
+            ("pending_total", snap.pending.to_string()),
+            ("stability", format!("{:.2}%", snap.stability)),
+            ("bitmap_cvg", format!("{:.2}%", snap.density)),
+            ("saved_crashes", snap.crashes.to_string()),
+            ("saved_hangs", snap.hangs.to_string()),
+            ("last_find", snap.last_find.to_string()),
+            ("last_crash", snap.last_crash.to_string()),
+            ("last_hang", snap.last_hang.to_string()),
+            ("edges_found", snap.edges.to_string()),
+            ("total_edges", self.global_branches.get_size().to_string()),
+            ("afl_banner", "fastgen".to_string()),
+            ("afl_version", env!("CARGO_PKG_VERSION").to_string()),
+            ("target_mode", "default".to_string()),
+            ("command_line", self.command_line.clone()),
+            ("seeds_tracked", snap.tracked.to_string()),
+            ("solutions_graded", snap.graded.to_string()),
+            ("branches_flipped", snap.flipped.to_string()),
+            ("branches_reached", snap.reached.to_string()),
+            ("branches_not_reached", snap.not_reached.to_string()),
+            ("solve_time_ms", (snap.solve_time_us / 1000).to_string()),
+            ("input_cache_hits", snap.cache_hits.to_string()),
+            ("input_cache_misses", snap.cache_misses.to_string()),
+            ("input_cache_bytes", snap.cache_size.to_string()),
+        ];
+        // a reader must not see half of it
+        let path = self.out_dir.join(defs::FUZZER_STATS_FILE);
+        let tmp_path = path.with_extension("tmp");
+        let ret =
+            fs::write(&tmp_path, format_stats(&fields)).and_then(|_| fs::rename(&tmp_path, &path));
+        if let Err(e) = ret {
+            warn!("Could not write {:?}: {:?}", path, e);
+        }
+
+        let line = format!(
+            "{}, 0, 0, {}, {}, 0, {:.2}%, {}, {}, 0, {:.2}, {}, {}, {}, {}, {}, {}",
+            snap.run_time,
+            snap.corpus_count,
+            snap.pending,
+            snap.density,
+            snap.crashes,
+            snap.hangs,
+            snap.execs_per_sec(),
+            snap.execs,
+            snap.edges,
+            snap.graded,
+            snap.flipped,
+            snap.reached,
+            snap.not_reached
+        );
+        if let Err(e) = writeln!(self.plot_data, "{}", line) {
+            warn!("Could not write plot data: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_stats() {
+        let fields = [
+            ("start_time", "1".to_string()),
+            ("execs_per_sec", "2.50".to_string()),
+        ];
+        assert_eq!(
+            format_stats(&fields),
+            "start_time        : 1\nexecs_per_sec     : 2.50\n"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+// An LRU cache of the queue inputs, bounded by their total size.
+// Every tracking run and every graded solution reads its input by id, so the
+// inputs of the seed being solved are read thousands of times.
+
+use std::collections::{BTreeMap, HashMap};
+
+pub struct InputCache {
+    capacity: usize,
+    size: usize,
+    tick: u64,
+    // id -> (buf, last use)
+    entries: HashMap<usize, (Vec<u8>, u64)>,
+    // last use -> id, the least recently used first
+    order: BTreeMap<u64, usize>,
+    num_hits: usize,
+    num_misses: usize,
+}
+
+impl InputCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            num_hits: 0,
+            num_misses: 0,
+        }
+    }
+
+    fn touch(&mut self, id: usize) -> u64 {
+        self.tick += 1;
+        if let Some(entry) = self.entries.get_mut(&id) {
+            self.order.remove(&entry.1);
+            entry.1 = self.tick;
+            self.order.insert(self.tick, id);
+        }
+        self.tick
+    }
+
+    pub fn get(&mut self, id: usize) -> Option<Vec<u8>> {
+        if self.entries.contains_key(&id) {
+            self.num_hits += 1;
+            self.touch(id);
+            self.entries.get(&id).map(|entry| entry.0.clone())
+        } else {
+            self.num_misses += 1;
+            None
+        }
+    }
+
+    pub fn insert(&mut self, id: usize, buf: &Vec<u8>) {
+        self.remove(id);
+        if buf.len() > self.capacity {
+            return;
+        }
+        while self.size + buf.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(&tick) => self.order[&tick],
+                None => break,
+            };
+            self.remove(oldest);
+        }
+        self.tick += 1;
+        self.size += buf.len();
+        self.entries.insert(id, (buf.clone(), self.tick));
+        self.order.insert(self.tick, id);
+    }
+
+    fn remove(&mut self, id: usize) {
+        if let Some((buf, tick)) = self.entries.remove(&id) {
+            self.size -= buf.len();
+            self.order.remove(&tick);
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // (hits, misses)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.num_hits, self.num_misses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru() {
+        let mut cache = InputCache::new(10);
+        cache.insert(0, &vec![0; 4]);
+        cache.insert(1, &vec![1; 4]);
+        assert_eq!(cache.get(0), Some(vec![0; 4]));
+        // 1 is the least recently used now
+        cache.insert(2, &vec![2; 4]);
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(2), Some(vec![2; 4]));
+        assert_eq!(cache.size(), 8);
+        // too big to cache, nothing is evicted for it
+        cache.insert(3, &vec![3; 11]);
+        assert_eq!(cache.get(3), None);
+        assert_eq!(cache.size(), 8);
+        assert_eq!(cache.stats(), (2, 2));
+    }
+}
@@ -10,9 +10,17 @@ pub mod util;
 //pub mod track_cons;
 pub mod afl;
 pub mod analyzer;
+pub mod bind_cpu;
+pub mod branch_stats;
 pub mod branches;
+pub mod calibrate;
+pub mod cgroup;
 pub mod check_dep;
+pub mod checkpoint;
+pub mod cmin;
+pub mod coverage;
 pub mod command;
+pub mod coordinate;
 pub mod cpp_interface;
 pub mod depot;
 pub mod depot_dir;
@@ -22,13 +30,23 @@ pub mod file;
 pub mod forksrv;
 pub mod fuzz_loop;
 pub mod fuzz_main;
+pub mod fuzz_stats;
+pub mod input_cache;
 pub mod interesting_val;
 pub mod limit;
 pub mod pipe_fd;
+pub mod provenance;
+pub mod scheduler;
 pub mod shm_conds;
 pub mod solution;
+pub mod source_map;
 pub mod status_type;
 pub mod sync;
+pub mod timeline;
+pub mod tmin;
 pub mod tmpfs;
+pub mod track_stats;
+pub mod tui;
 pub mod union_find;
+pub mod watchdog;
 pub mod z3solver;
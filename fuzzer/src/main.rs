@@ -1,20 +1,27 @@
 #[macro_use]
 extern crate clap;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::str::FromStr;
 
 //extern crate angora;
 //extern crate angora_common;
+use fastgen::branch_stats::report_branches;
+use fastgen::cmin::cmin_main;
+use fastgen::coverage::coverage_main;
 use fastgen::fuzz_main::*;
+use fastgen::source_map::SourceMap;
+use fastgen::tmin::tmin_main;
 
 fn main() {
     let matches = App::new("angora-fuzzer")
         .version(crate_version!())
         .about("Fastgen is a mutation-based fuzzer.")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("input_dir")
              .short("i")
              .long("input")
              .value_name("DIR")
-             .help("Sets the directory of input seeds, use \"-\" to restart with existing output directory")
+             .help("Sets the directory of input seeds, use \"-\" to resume from the checkpoint in the existing output directory")
              .takes_value(true)
              .required(true))
         .arg(Arg::with_name("output_dir")
@@ -47,7 +54,7 @@ fn main() {
              .short("T")
              .long("time_limit")
              .value_name("TIME")
-             .help("time limit for programs, default is 1(s), the tracking timeout is 12 * TIME")
+             .help("Time limit for programs in milliseconds, calibrated from the seeds (at most 1000) if not set")
              .takes_value(true))
         .arg(Arg::with_name("thread_jobs")
              .short("j")
@@ -64,19 +71,274 @@ fn main() {
         .arg(Arg::with_name("executor_timeout")
              .long("executor_timeout")
              .value_name("EXECUTOR_TIMEOUT")
-             .help("Timeout in seconds for the executor")
+             .help("Timeout in milliseconds for the tracking executor, calibrated from the seeds (at most 120000) if not set")
              .takes_value(true))
         .arg(Arg::with_name("solver_timeout")
              .long("solver_timeout")
              .value_name("SOLVER_TIMEOUT")
              .help("Timeout in seconds for the solver")
              .takes_value(true))
+        .arg(Arg::with_name("cgroup")
+             .long("cgroup")
+             .value_name("DIR")
+             .help("Enforce limits with cgroups v2 created under DIR (a delegated cgroup) instead of RLIMIT_AS")
+             .takes_value(true))
+        .arg(Arg::with_name("schedule")
+             .long("schedule")
+             .value_name("POLICY")
+             .help("Order in which seeds are tracked and solved, default is fifo")
+             .possible_values(&["fifo", "newest", "fewest_edges", "smallest"])
+             .takes_value(true))
+        .arg(Arg::with_name("max_len")
+             .long("max-len")
+             .value_name("BYTES")
+             .help("Maximum length of inputs, longer seeds and synced inputs are skipped, default is 1000000")
+             .takes_value(true))
+        .arg(Arg::with_name("truncate_seeds")
+             .long("truncate-seeds")
+             .help("Cut seeds longer than the maximum length instead of skipping them"))
+        .arg(Arg::with_name("tui")
+             .long("tui")
+             .help("Show a status screen instead of scrolling logs, redirect stderr to keep the log off it. Without a terminal, log a summary every minute."))
+        .arg(Arg::with_name("cond_locs")
+             .long("cond-locs")
+             .value_name("FILE")
+             .help("Sets the pass log of a build with ANGORA_OUTPUT_COND_LOC=1, to show the source locations of branches")
+             .takes_value(true))
+        .arg(Arg::with_name("dwarf_locs")
+             .long("dwarf-locs")
+             .help("Look up the branches that are not in --cond-locs in the debug info of the track binary with addr2line. Only for binaries that are not position independent."))
+        .arg(Arg::with_name("map_size")
+             .long("map-size")
+             .value_name("BYTES")
+             .help("Size of the coverage map, a power of two from 65536 to 16777216, default is 1048576, or the size of the output directory when resuming. Larger maps have fewer collisions on big targets.")
+             .takes_value(true))
+        .arg(Arg::with_name("coverage")
+             .long("coverage")
+             .value_name("MODE")
+             .help("Coverage feedback: edge, context (edges in their calling context) or ngram-N (the last N blocks, N from 3 to 7). Default is context, or the mode of the output directory when resuming.")
+             .takes_value(true)
+             .validator(is_coverage_mode))
+        .arg(Arg::with_name("watch")
+             .long("watch")
+             .value_name("DIR")
+             .help("Import the inputs that other tools write to DIR as soon as they are written, can be given more than once")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1))
         .arg(Arg::with_name("sync_afl")
              .short("S")
              .long("sync_afl")
-             .help("Sync the seeds with AFL. Output directory should be in AFL's directory structure."))
+             .help("Sync the seeds with AFL. Output directory should be in AFL's directory structure. The queues and crashes of all the other fuzzers in it are imported, and angora/ is a peer that AFL++ syncs from."))
+        .arg(Arg::with_name("instance")
+             .long("instance")
+             .value_name("NAME")
+             .help("Name of this instance's directory in the sync directory with -S, default is angora. Every instance of one sync directory needs its own name.")
+             .takes_value(true))
+        .arg(Arg::with_name("coordinate")
+             .long("coordinate")
+             .help("With -S, split the seeds with the other fastgen instances that coordinate on the sync directory, and share which branches were hit and flipped"))
+        .subcommand(SubCommand::with_name("cmin")
+            .about("Copy the smallest inputs that keep the coverage of a corpus to a new directory")
+            .arg(Arg::with_name("input_dir")
+                 .short("i")
+                 .long("input")
+                 .value_name("DIR")
+                 .help("Sets the corpus to minimize, e.g. a queue directory")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("output_dir")
+                 .short("o")
+                 .long("output")
+                 .value_name("DIR")
+                 .help("Sets the directory of the minimized corpus")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("memory_limit")
+                 .short("M")
+                 .long("memory_limit")
+                 .value_name("MEM")
+                 .help("Memory limit for programs, default is 200(MB), set 0 for unlimit memory")
+                 .takes_value(true))
+            .arg(Arg::with_name("time_limit")
+                 .short("T")
+                 .long("time_limit")
+                 .value_name("TIME")
+                 .help("Time limit for programs in milliseconds, default is 1000")
+                 .takes_value(true))
+            .arg(Arg::with_name("coverage")
+                 .long("coverage")
+                 .value_name("MODE")
+                 .help("Coverage feedback as in fuzzing. Default is the mode recorded in the parent of the input directory, e.g. of a queue, else context.")
+                 .takes_value(true)
+                 .validator(is_coverage_mode))
+            .arg(Arg::with_name("map_size")
+                 .long("map-size")
+                 .value_name("BYTES")
+                 .help("Size of the coverage map as in fuzzing. Default is the size recorded in the parent of the input directory, else 1048576.")
+                 .takes_value(true))
+            .arg(Arg::with_name("max_len")
+                 .long("max-len")
+                 .value_name("BYTES")
+                 .help("Maximum length of inputs as in fuzzing, longer ones are skipped, default is 1000000")
+                 .takes_value(true))
+            .arg(Arg::with_name("pargs")
+                 .help("Targeted program (USE_FAST) and arguments. Any \"@@\" will be substituted with the input filename.")
+                 .required(true)
+                 .multiple(true)
+                 .allow_hyphen_values(true)
+                 .last(true)
+                 .index(1)))
+        .subcommand(SubCommand::with_name("tmin")
+            .about("Shrink a crashing or hanging input while keeping the way it fails")
+            .arg(Arg::with_name("input")
+                 .short("i")
+                 .long("input")
+                 .value_name("FILE")
+                 .help("Sets the input to shrink")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("output")
+                 .short("o")
+                 .long("output")
+                 .value_name("FILE")
+                 .help("Sets the file to write the shrunk input to")
+                 .takes_value(true)
+                 .required(true))
+            .arg(Arg::with_name("track_target")
+                 .short("t")
+                 .long("track")
+                 .value_name("PROM")
+                 .help("Sets the target (USE_TRACK) to find the bytes that are not tainted, which are removed first")
+                 .takes_value(true))
+            .arg(Arg::with_name("memory_limit")
+                 .short("M")
+                 .long("memory_limit")
+                 .value_name("MEM")
+                 .help("Memory limit for programs, default is 200(MB), set 0 for unlimit memory")
+                 .takes_value(true))
+            .arg(Arg::with_name("time_limit")
+                 .short("T")
+                 .long("time_limit")
+                 .value_name("TIME")
+                 .help("Time limit for programs in milliseconds, default is 1000")
+                 .takes_value(true))
+            .arg(Arg::with_name("pargs")
+                 .help("Targeted program (USE_FAST) and arguments. Any \"@@\" will be substituted with the input filename.")
+                 .required(true)
+                 .multiple(true)
+                 .allow_hyphen_values(true)
+                 .last(true)
+                 .index(1)))
+        .subcommand(SubCommand::with_name("report")
+            .about("Summarize the state of a campaign from its output directory")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("branches")
+                .about("List the branches that were tried the most but never flipped, and why")
+                .arg(Arg::with_name("output_dir")
+                     .short("o")
+                     .long("output")
+                     .value_name("DIR")
+                     .help("Sets the output directory of the campaign")
+                     .takes_value(true)
+                     .required(true))
+                .arg(Arg::with_name("num")
+                     .short("n")
+                     .long("num")
+                     .value_name("NUM")
+                     .help("Number of branches to list, default is 20")
+                     .takes_value(true))
+                .arg(Arg::with_name("track_target")
+                     .short("t")
+                     .long("track")
+                     .value_name("PROM")
+                     .help("Sets the track binary, to look up the source locations in its debug info")
+                     .takes_value(true))
+                .arg(Arg::with_name("cond_locs")
+                     .long("cond-locs")
+                     .value_name("FILE")
+                     .help("Sets the pass log of a build with ANGORA_OUTPUT_COND_LOC=1, to show the source locations")
+                     .takes_value(true)))
+            .subcommand(SubCommand::with_name("coverage")
+                .about("Write the line coverage of a queue as an lcov tracefile, using a sancov build of the target")
+                .arg(Arg::with_name("input_dir")
+                     .short("i")
+                     .long("input")
+                     .value_name("DIR")
+                     .help("Sets the inputs to run, e.g. a queue directory")
+                     .takes_value(true)
+                     .required(true))
+                .arg(Arg::with_name("output_dir")
+                     .short("o")
+                     .long("output")
+                     .value_name("DIR")
+                     .help("Sets the directory of the report")
+                     .takes_value(true)
+                     .required(true))
+                .arg(Arg::with_name("time_limit")
+                     .short("T")
+                     .long("time_limit")
+                     .value_name("TIME")
+                     .help("Time limit for programs in milliseconds, default is 1000")
+                     .takes_value(true))
+                .arg(Arg::with_name("html")
+                     .long("html")
+                     .help("Also render the tracefile as HTML with genhtml"))
+                .arg(Arg::with_name("pargs")
+                     .help("Program built with -fsanitize-coverage and arguments. Any \"@@\" will be substituted with the input filename.")
+                     .required(true)
+                     .multiple(true)
+                     .allow_hyphen_values(true)
+                     .last(true)
+                     .index(1))))
        .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("cmin") {
+        cmin_main(
+            matches.value_of("input_dir").unwrap(),
+            matches.value_of("output_dir").unwrap(),
+            matches.values_of_lossy("pargs").unwrap(),
+            value_t!(matches, "memory_limit", u64).unwrap_or(fastgen_common::config::MEM_LIMIT),
+            value_t!(matches, "time_limit", u64).unwrap_or(fastgen_common::config::TIME_LIMIT),
+            matches.value_of("coverage").map(|m| m.parse().unwrap()),
+            opt_value(matches, "map_size"),
+            opt_value(matches, "max_len").unwrap_or(fastgen_common::config::MAX_INPUT_LEN),
+        );
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("tmin") {
+        tmin_main(
+            matches.value_of("input").unwrap(),
+            matches.value_of("output").unwrap(),
+            matches.value_of("track_target"),
+            matches.values_of_lossy("pargs").unwrap(),
+            value_t!(matches, "memory_limit", u64).unwrap_or(fastgen_common::config::MEM_LIMIT),
+            value_t!(matches, "time_limit", u64).unwrap_or(fastgen_common::config::TIME_LIMIT),
+        );
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("report") {
+        if let Some(matches) = matches.subcommand_matches("branches") {
+            report_branches(
+                matches.value_of("output_dir").unwrap(),
+                value_t!(matches, "num", usize).unwrap_or(20),
+                &SourceMap::new(matches.value_of("cond_locs"), matches.value_of("track_target")),
+            );
+        }
+        if let Some(matches) = matches.subcommand_matches("coverage") {
+            coverage_main(
+                matches.value_of("input_dir").unwrap(),
+                matches.value_of("output_dir").unwrap(),
+                matches.values_of_lossy("pargs").unwrap(),
+                value_t!(matches, "time_limit", u64).unwrap_or(fastgen_common::config::TIME_LIMIT),
+                matches.is_present("html"),
+            );
+        }
+        return;
+    }
+
     fuzz_main(
         matches.value_of("input_dir").unwrap(),
         matches.value_of("output_dir").unwrap(),
@@ -85,9 +347,36 @@ fn main() {
         value_t!(matches, "thread_jobs", usize).unwrap_or(1),
         value_t!(matches, "grader_jobs", usize).unwrap_or(1),
         value_t!(matches, "memory_limit", u64).unwrap_or(fastgen_common::config::MEM_LIMIT),
-        value_t!(matches, "time_limit", u64).unwrap_or(fastgen_common::config::TIME_LIMIT),
+        value_t!(matches, "time_limit", u64).ok(),
         matches.occurrences_of("sync_afl") > 0,
-        value_t!(matches, "executor_timeout", usize).unwrap_or(120) as u64,
+        value_t!(matches, "executor_timeout", u64).ok(),
         value_t!(matches, "solver_timeout", usize).unwrap_or(10) as u64,
+        matches.value_of("cgroup"),
+        matches.value_of("schedule").unwrap_or("fifo").parse().unwrap(),
+        opt_value(&matches, "max_len").unwrap_or(fastgen_common::config::MAX_INPUT_LEN),
+        matches.is_present("truncate_seeds"),
+        matches.is_present("tui"),
+        matches.value_of("cond_locs"),
+        opt_value(&matches, "map_size"),
+        matches.value_of("coverage").map(|m| m.parse().unwrap()),
+        matches.values_of("watch").map(|dirs| dirs.collect()).unwrap_or_default(),
+        matches.value_of("instance").unwrap_or(fastgen_common::defs::ANGORA_DIR_NAME),
+        matches.is_present("coordinate"),
+        matches.is_present("dwarf_locs"),
     );
 }
+
+fn is_coverage_mode(mode: String) -> Result<(), String> {
+    mode.parse::<fastgen_common::coverage_mode::CoverageMode>()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+// None if the option is not given, exits on a value that does not parse.
+fn opt_value<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    if matches.is_present(name) {
+        Some(value_t!(matches, name, T).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    }
+}
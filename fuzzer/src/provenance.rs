@@ -0,0 +1,248 @@
+// Where a saved input came from, written as a JSON sidecar to
+// meta/{queue,crashes,hangs,ooms}/id:NNNNNN.json. The sidecars live outside
+// the input directories because AFL syncs every id:* file in queue/.
+
+use crate::{
+    file::parse_file_id, solution::Solution, source_map::SourceMap, status_type::StatusType,
+};
+use chrono::prelude::Local;
+use std::{fmt::Write, path::Path, time};
+
+#[derive(Debug, Clone)]
+pub struct SolverOrigin {
+    pub parent: u32,
+    pub addr: u64,
+    pub ctx: u64,
+    pub order: u32,
+    pub direction: u64,
+    pub bid: u32,
+    pub sctx: u32,
+    pub msgtype: u32,
+    pub nested: bool,
+    pub solve_time: time::Duration,
+    // file:line of the branch, if known
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    // "seed", "sync", "watch", "solver", "afl-mutator" or "unknown"
+    pub source: &'static str,
+    // the file it was imported from
+    pub from: Option<String>,
+    pub solver: Option<SolverOrigin>,
+}
+
+impl Default for Provenance {
+    fn default() -> Self {
+        Self {
+            source: "unknown",
+            from: None,
+            solver: None,
+        }
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn status_name(status: StatusType) -> &'static str {
+    match status {
+        StatusType::Normal => "normal",
+        StatusType::Timeout => "timeout",
+        StatusType::Crash => "crash",
+        StatusType::Oom => "oom",
+        StatusType::Skip => "skip",
+        StatusType::Error => "error",
+    }
+}
+
+fn msgtype_name(msgtype: u32) -> &'static str {
+    match msgtype {
+        0 => "cond",
+        1 => "gep",
+        2 => "strcmp",
+        3 => "offset",
+        _ => "size",
+    }
+}
+
+impl Provenance {
+    pub fn imported(source: &'static str, from: &str) -> Self {
+        Self {
+            source,
+            from: Some(from.to_string()),
+            solver: None,
+        }
+    }
+
+    pub fn mutated() -> Self {
+        Self {
+            source: "afl-mutator",
+            from: None,
+            solver: None,
+        }
+    }
+
+    // The location is looked up with locate once the input is saved.
+    pub fn solved(sol: &Solution) -> Self {
+        Self {
+            source: "solver",
+            from: None,
+            solver: Some(SolverOrigin {
+                parent: sol.fid,
+                addr: sol.addr,
+                ctx: sol.ctx,
+                order: sol.order,
+                direction: sol.direction,
+                bid: sol.bid,
+                sctx: sol.sctx,
+                msgtype: sol.msgtype,
+                nested: sol.nested,
+                solve_time: sol.solve_time,
+                location: None,
+            }),
+        }
+    }
+
+    pub fn locate(&mut self, source_map: &SourceMap) {
+        if let Some(ref mut o) = self.solver {
+            if o.location.is_none() {
+                o.location = source_map.locate(o.bid, o.addr).map(|loc| loc.to_string());
+            }
+        }
+    }
+
+    // The rest of the file name after "id:NNNNNN,", in AFL's words: where it
+    // came from, and "+cov" if it has new edges.
+    pub fn afl_desc(&self, new_cov: bool) -> String {
+        let path = self.from.as_ref().map(|from| Path::new(from));
+        let mut desc = match (self.source, path) {
+            ("solver", _) => match self.solver {
+                Some(ref o) => format!("src:{:06},op:fastgen", o.parent),
+                None => "op:fastgen".to_string(),
+            },
+            ("seed", Some(path)) | ("watch", Some(path)) => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                // keep the file name short enough
+                format!("orig:{}", name.chars().take(128).collect::<String>())
+            }
+            ("sync", Some(path)) => {
+                // <sync_dir>/<peer>/queue/id:NNNNNN,...
+                let peer = path.parent().and_then(|p| p.parent()).and_then(|p| p.file_name());
+                let id = path.file_name().and_then(|n| parse_file_id(&n.to_string_lossy()));
+                match (peer, id) {
+                    (Some(peer), Some(id)) => {
+                        format!("sync:{},src:{:06}", peer.to_string_lossy(), id)
+                    }
+                    _ => "sync:unknown".to_string(),
+                }
+            }
+            // the havoc of afl.rs, its parent is not recorded
+            ("afl-mutator", _) => "op:havoc".to_string(),
+            _ => "op:fastgen".to_string(),
+        };
+        if new_cov {
+            desc.push_str(",+cov");
+        }
+        desc
+    }
+
+    pub fn to_json(&self, id: usize, status: StatusType) -> String {
+        self.to_json_at(id, status, &Local::now().to_rfc3339())
+    }
+
+    fn to_json_at(&self, id: usize, status: StatusType, time: &str) -> String {
+        let mut s = String::new();
+        let _ = write!(
+            s,
+            "{{\"id\":{},\"status\":\"{}\",\"source\":\"{}\",\"time\":\"{}\"",
+            id,
+            status_name(status),
+            self.source,
+            time
+        );
+        if let Some(ref from) = self.from {
+            let _ = write!(s, ",\"from\":\"{}\"", escape_json(from));
+        }
+        if let Some(ref o) = self.solver {
+            let _ = write!(
+                s,
+                ",\"parent\":{},\"addr\":\"{:#x}\",\"ctx\":\"{:#x}\",\"order\":{},\"direction\":{},\
+                 \"bid\":{},\"sctx\":{},\"msg_type\":\"{}\",\"variant\":\"{}\",\"solve_time_us\":{}",
+                o.parent,
+                o.addr,
+                o.ctx,
+                o.order,
+                o.direction,
+                o.bid,
+                o.sctx,
+                msgtype_name(o.msgtype),
+                if o.nested { "nested" } else { "optimistic" },
+                o.solve_time.as_micros()
+            );
+            if let Some(ref location) = o.location {
+                let _ = write!(s, ",\"location\":\"{}\"", escape_json(location));
+            }
+        }
+        s.push('}');
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let prov = Provenance::imported("sync", "fuzzer01/queue/id:000001,\"x\"");
+        assert_eq!(
+            prov.to_json_at(3, StatusType::Normal, "t"),
+            "{\"id\":3,\"status\":\"normal\",\"source\":\"sync\",\"time\":\"t\",\
+             \"from\":\"fuzzer01/queue/id:000001,\\\"x\\\"\"}"
+        );
+        assert_eq!(prov.afl_desc(false), "sync:fuzzer01,src:000001");
+        assert_eq!(Provenance::imported("seed", "in/a.png").afl_desc(true), "orig:a.png,+cov");
+        assert_eq!(Provenance::mutated().afl_desc(true), "op:havoc,+cov");
+
+        let prov = Provenance {
+            source: "solver",
+            from: None,
+            solver: Some(SolverOrigin {
+                parent: 1,
+                addr: 0x4005d0,
+                ctx: 0,
+                order: 2,
+                direction: 1,
+                bid: 7,
+                sctx: 8,
+                msgtype: 0,
+                nested: true,
+                solve_time: time::Duration::from_micros(1500),
+                location: Some("foo.c:12:7".to_string()),
+            }),
+        };
+        assert_eq!(
+            prov.to_json_at(4, StatusType::Crash, "t"),
+            "{\"id\":4,\"status\":\"crash\",\"source\":\"solver\",\"time\":\"t\",\
+             \"parent\":1,\"addr\":\"0x4005d0\",\"ctx\":\"0x0\",\"order\":2,\"direction\":1,\
+             \"bid\":7,\"sctx\":8,\"msg_type\":\"cond\",\"variant\":\"nested\",\"solve_time_us\":1500,\
+             \"location\":\"foo.c:12:7\"}"
+        );
+        assert_eq!(prov.afl_desc(true), "src:000001,op:fastgen,+cov");
+    }
+}
@@ -0,0 +1,227 @@
+// Which seed in the queue is tracked and solved next.
+// Seeds that are handed back with `requeue`, e.g. because their solving hit
+// the time cap, always come after the seeds that were never tried.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, VecDeque},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchedulePolicy {
+    // in the order they were saved, as before
+    Fifo,
+    // the most recent coverage first
+    Newest,
+    // the fewest edges on the path first. The unsolved branches of a seed are
+    // only known once it is tracked, the path length stands in for them.
+    FewestEdges,
+    Smallest,
+}
+
+impl FromStr for SchedulePolicy {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fifo" => Ok(SchedulePolicy::Fifo),
+            "newest" => Ok(SchedulePolicy::Newest),
+            "fewest_edges" => Ok(SchedulePolicy::FewestEdges),
+            "smallest" => Ok(SchedulePolicy::Smallest),
+            _ => Err("unknown schedule policy"),
+        }
+    }
+}
+
+// The path length of a seed that was not run, e.g. found after a checkpoint.
+// It goes after the seeds whose path length is known.
+pub const UNKNOWN_EDGES: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seed {
+    pub id: usize,
+    pub len: usize,
+    pub num_edges: usize,
+    // how many times it has been handed back
+    pub requeued: usize,
+}
+
+impl Seed {
+    pub fn new(id: usize, len: usize, num_edges: usize) -> Self {
+        Self {
+            id,
+            len,
+            num_edges,
+            requeued: 0,
+        }
+    }
+}
+
+pub trait SeedScheduler: Send {
+    fn push(&mut self, seed: Seed);
+    fn pop(&mut self) -> Option<Seed>;
+    fn len(&self) -> usize;
+    // the queued seeds in no particular order, for checkpoints
+    fn seeds(&self) -> Vec<Seed>;
+}
+
+pub fn new_scheduler(policy: SchedulePolicy) -> Box<dyn SeedScheduler> {
+    match policy {
+        SchedulePolicy::Fifo => Box::new(FifoScheduler::default()),
+        _ => Box::new(PriorityScheduler::new(policy)),
+    }
+}
+
+#[derive(Default)]
+pub struct FifoScheduler {
+    fresh: VecDeque<Seed>,
+    requeued: VecDeque<Seed>,
+}
+
+impl SeedScheduler for FifoScheduler {
+    fn push(&mut self, seed: Seed) {
+        if seed.requeued == 0 {
+            self.fresh.push_back(seed);
+        } else {
+            self.requeued.push_back(seed);
+        }
+    }
+
+    fn pop(&mut self) -> Option<Seed> {
+        self.fresh.pop_front().or_else(|| self.requeued.pop_front())
+    }
+
+    fn len(&self) -> usize {
+        self.fresh.len() + self.requeued.len()
+    }
+
+    fn seeds(&self) -> Vec<Seed> {
+        self.fresh.iter().chain(self.requeued.iter()).cloned().collect()
+    }
+}
+
+// BinaryHeap pops the greatest entry.
+struct Entry {
+    key: (Reverse<usize>, Reverse<usize>, Reverse<usize>),
+    seed: Seed,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+pub struct PriorityScheduler {
+    policy: SchedulePolicy,
+    heap: BinaryHeap<Entry>,
+}
+
+impl PriorityScheduler {
+    pub fn new(policy: SchedulePolicy) -> Self {
+        Self {
+            policy,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn key(&self, seed: &Seed) -> (Reverse<usize>, Reverse<usize>, Reverse<usize>) {
+        let prio = match self.policy {
+            SchedulePolicy::Fifo => seed.id,
+            SchedulePolicy::Newest => usize::MAX - seed.id,
+            SchedulePolicy::FewestEdges => seed.num_edges,
+            SchedulePolicy::Smallest => seed.len,
+        };
+        // ties go to the older seed
+        (Reverse(seed.requeued), Reverse(prio), Reverse(seed.id))
+    }
+}
+
+impl SeedScheduler for PriorityScheduler {
+    fn push(&mut self, seed: Seed) {
+        let key = self.key(&seed);
+        self.heap.push(Entry { key, seed });
+    }
+
+    fn pop(&mut self) -> Option<Seed> {
+        self.heap.pop().map(|entry| entry.seed)
+    }
+
+    fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    fn seeds(&self) -> Vec<Seed> {
+        self.heap.iter().map(|entry| entry.seed).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pop_ids(sched: &mut Box<dyn SeedScheduler>) -> Vec<usize> {
+        let mut ids = vec![];
+        while let Some(seed) = sched.pop() {
+            ids.push(seed.id);
+        }
+        ids
+    }
+
+    fn push_seeds(sched: &mut Box<dyn SeedScheduler>) {
+        sched.push(Seed::new(0, 30, 5));
+        sched.push(Seed::new(1, 10, 9));
+        sched.push(Seed::new(2, 20, 1));
+    }
+
+    #[test]
+    fn test_policies() {
+        let expected = [
+            (SchedulePolicy::Fifo, vec![0, 1, 2]),
+            (SchedulePolicy::Newest, vec![2, 1, 0]),
+            (SchedulePolicy::FewestEdges, vec![2, 0, 1]),
+            (SchedulePolicy::Smallest, vec![1, 2, 0]),
+        ];
+        for (policy, ids) in expected.iter() {
+            let mut sched = new_scheduler(*policy);
+            push_seeds(&mut sched);
+            assert_eq!(sched.len(), 3);
+            assert_eq!(&pop_ids(&mut sched), ids, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_requeue_last() {
+        for policy in [SchedulePolicy::Fifo, SchedulePolicy::Smallest].iter() {
+            let mut sched = new_scheduler(*policy);
+            let mut seed = Seed::new(3, 1, 1);
+            seed.requeued = 1;
+            sched.push(seed);
+            push_seeds(&mut sched);
+            assert_eq!(pop_ids(&mut sched).last(), Some(&3));
+        }
+    }
+
+    #[test]
+    fn test_unknown_edges() {
+        let mut sched = new_scheduler(SchedulePolicy::FewestEdges);
+        sched.push(Seed::new(3, 1, UNKNOWN_EDGES));
+        push_seeds(&mut sched);
+        assert_eq!(pop_ids(&mut sched), vec![2, 0, 1, 3]);
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::time;
 pub struct Solution {
     pub sol: HashMap<u32, u8>,
     pub fid: u32,
@@ -13,6 +14,11 @@ pub struct Solution {
     pub is_cmp: bool,
     pub predicate: u32,
     pub target_cond: u64,
+    // the kind of message it was solved from, 0 for cond and 1 for gep
+    pub msgtype: u32,
+    // solved with the nested constraints too, not just optimistically
+    pub nested: bool,
+    pub solve_time: time::Duration,
 }
 
 impl Solution {
@@ -30,6 +36,9 @@ impl Solution {
         is_cmp: bool,
         predicate: u32,
         target_cond: u64,
+        msgtype: u32,
+        nested: bool,
+        solve_time: time::Duration,
     ) -> Self {
         Self {
             sol: sol,
@@ -45,6 +54,9 @@ impl Solution {
             is_cmp: is_cmp,
             predicate: predicate,
             target_cond: target_cond,
+            msgtype: msgtype,
+            nested: nested,
+            solve_time: solve_time,
         }
     }
 }
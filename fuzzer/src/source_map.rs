@@ -0,0 +1,177 @@
+// Source locations of the branches, for the logs, the branch report and the
+// provenance of saved inputs. They come from two places:
+// - the pass log of a build with ANGORA_OUTPUT_COND_LOC=1, where each branch
+//   id (bid) is printed as "[ID] <bid>" and then "[LOC] <file>, Ln <l>, Col <c>";
+// - the DWARF line info of the track binary, looked up by addr2line with the
+//   return address that the runtime reports as addr. This only works if the
+//   binary is not position independent, so fuzzing does it with --dwarf-locs.
+// While fuzzing, only the branches of saved inputs are located.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs,
+    io::{self, prelude::*, BufReader},
+    process::{Command, Stdio},
+    sync::Mutex,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLoc {
+    pub file: String,
+    pub line: u32,
+    // 0 if unknown, addr2line has no columns
+    pub col: u32,
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.col == 0 {
+            write!(f, "{}:{}", self.file, self.line)
+        } else {
+            write!(f, "{}:{}:{}", self.file, self.line, self.col)
+        }
+    }
+}
+
+// "[LOC] foo.c, Ln 12, Col 7"
+fn parse_loc_line(s: &str) -> Option<SourceLoc> {
+    let s = s.strip_prefix("[LOC] ")?;
+    let mut parts = s.rsplitn(3, ", ");
+    let col = parts.next()?.strip_prefix("Col ")?.parse().ok()?;
+    let line = parts.next()?.strip_prefix("Ln ")?.parse().ok()?;
+    let file = parts.next()?.to_string();
+    Some(SourceLoc { file, line, col })
+}
+
+pub fn parse_cond_locs<R: BufRead>(r: R) -> HashMap<u32, SourceLoc> {
+    let mut locs = HashMap::new();
+    let mut id = None;
+    for line in r.lines().filter_map(|l| l.ok()) {
+        if let Some(s) = line.strip_prefix("[ID] ") {
+            id = s.trim().parse::<u32>().ok();
+        } else if let Some(loc) = parse_loc_line(&line) {
+            // AngoraPass prints the instruction in between
+            if let Some(id) = id.take() {
+                locs.insert(id, loc);
+            }
+        }
+    }
+    locs
+}
+
+// "foo.c:12" or "foo.c:12 (discriminator 3)", "??:0" or "??:?" if unknown
+fn parse_addr2line(s: &str) -> Option<SourceLoc> {
+    let s = s.split(" (").next()?;
+    let pos = s.rfind(':')?;
+    let file = &s[..pos];
+    let line = s[pos + 1..].parse().ok()?;
+    if file == "??" || line == 0 {
+        return None;
+    }
+    Some(SourceLoc {
+        file: file.to_string(),
+        line,
+        col: 0,
+    })
+}
+
+// Look up many addresses with one addr2line run.
+pub fn symbolize(bin: &str, addrs: &[u64]) -> io::Result<Vec<Option<SourceLoc>>> {
+    if addrs.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut child = Command::new("addr2line")
+        .arg("-e")
+        .arg(bin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        for addr in addrs {
+            writeln!(stdin, "{:#x}", addr)?;
+        }
+    }
+    let output = child.wait_with_output()?;
+    let mut locs: Vec<_> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(parse_addr2line)
+        .collect();
+    locs.resize(addrs.len(), None);
+    Ok(locs)
+}
+
+pub struct SourceMap {
+    cond_locs: HashMap<u32, SourceLoc>,
+    track_bin: Option<String>,
+    // addr -> location, None if addr2line does not know it
+    addrs: Mutex<HashMap<u64, Option<SourceLoc>>>,
+}
+
+impl SourceMap {
+    pub fn new(cond_locs_file: Option<&str>, track_bin: Option<&str>) -> Self {
+        let cond_locs = match cond_locs_file {
+            Some(path) => {
+                let f = fs::File::open(path).expect("Could not open the condition locations.");
+                let locs = parse_cond_locs(BufReader::new(f));
+                info!("Loaded {} condition locations from {}", locs.len(), path);
+                locs
+            }
+            None => HashMap::new(),
+        };
+        Self {
+            cond_locs,
+            track_bin: track_bin.map(|bin| bin.to_string()),
+            addrs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn lookup_addr(&self, addr: u64) -> Option<SourceLoc> {
+        let bin = self.track_bin.as_ref()?;
+        if addr == 0 {
+            return None;
+        }
+        if let Some(loc) = self.addrs.lock().unwrap().get(&addr) {
+            return loc.clone();
+        }
+        // addr is a return address, the call is right before it
+        let loc = match symbolize(bin, &[addr - 1]) {
+            Ok(mut locs) => locs.pop().unwrap_or(None),
+            Err(e) => {
+                warn!("Could not run addr2line on {}: {:?}", bin, e);
+                None
+            }
+        };
+        self.addrs.lock().unwrap().insert(addr, loc.clone());
+        loc
+    }
+
+    // The pass log knows the branch itself, DWARF only the call into the runtime.
+    pub fn locate(&self, bid: u32, addr: u64) -> Option<SourceLoc> {
+        match self.cond_locs.get(&bid) {
+            Some(loc) => Some(loc.clone()),
+            None => self.lookup_addr(addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_locs() {
+        let log = "Track Mode.\n[ID] 12345\n[INS]   %cmp = icmp eq i32 %0, 7\n\
+                   [LOC] src/a, b.c, Ln 12, Col 7\n[ID] 99\n[INS]   %c = icmp\n[ID] 100\n";
+        let locs = parse_cond_locs(log.as_bytes());
+        assert_eq!(locs.len(), 1);
+        assert_eq!(locs[&12345].to_string(), "src/a, b.c:12:7");
+
+        assert_eq!(parse_addr2line("/src/foo.c:42").unwrap().to_string(), "/src/foo.c:42");
+        assert_eq!(parse_addr2line("foo.c:3 (discriminator 2)").unwrap().line, 3);
+        assert_eq!(parse_addr2line("??:0"), None);
+        assert_eq!(parse_addr2line("??:?"), None);
+    }
+}
@@ -3,6 +3,7 @@ pub enum StatusType {
     Normal,
     Timeout,
     Crash,
+    Oom,
     Skip,
     Error,
 }
@@ -1,17 +1,89 @@
 use crate::executor::Executor;
 use crate::file::*;
-use fastgen_common::{config, defs};
+use crate::provenance::Provenance;
+use crate::status_type::StatusType;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use fastgen_common::defs;
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+};
 use std::{
     collections::HashMap,
-    fs,
-    path::Path,
+    fmt, fs,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
+    thread, time,
 };
 
-pub fn sync_depot(executor: &mut Executor, running: Arc<AtomicBool>, dir: &Path) {
+// What happened to the inputs of one import.
+#[derive(Default)]
+struct ImportStats {
+    num_new: usize,
+    num_no_new: usize,
+    num_crashes: usize,
+    num_hangs: usize,
+    num_too_long: usize,
+    num_errors: usize,
+    // cut to the maximum length and run, counted in the above too
+    num_truncated: usize,
+}
+
+impl ImportStats {
+    fn run(&mut self, executor: &mut Executor, buf: &Vec<u8>, prov: Provenance) {
+        executor.provenance = Some(prov);
+        let (status, has_new_path) = executor.run_import(buf);
+        if has_new_path {
+            if let Some(ref stats) = executor.stats {
+                stats.num_imported.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        match status {
+            StatusType::Normal if has_new_path => self.num_new += 1,
+            StatusType::Normal => self.num_no_new += 1,
+            StatusType::Crash | StatusType::Oom => self.num_crashes += 1,
+            StatusType::Timeout | StatusType::Skip => self.num_hangs += 1,
+            StatusType::Error => self.num_errors += 1,
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.num_new
+            + self.num_no_new
+            + self.num_crashes
+            + self.num_hangs
+            + self.num_too_long
+            + self.num_errors
+    }
+}
+
+impl fmt::Display for ImportStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} queued; skipped {} without new coverage, {} crashes, {} hangs, {} too long, {} errors",
+            self.num_new,
+            self.total(),
+            self.num_no_new,
+            self.num_crashes,
+            self.num_hangs,
+            self.num_too_long,
+            self.num_errors
+        )?;
+        if self.num_truncated > 0 {
+            write!(f, ", {} truncated", self.num_truncated)?;
+        }
+        Ok(())
+    }
+}
+
+// Seeds longer than the maximum length are dropped, or cut to it if `truncate`.
+pub fn sync_depot(executor: &mut Executor, running: Arc<AtomicBool>, dir: &Path, truncate: bool) {
+    let mut stats = ImportStats::default();
     let seed_dir = dir.read_dir().expect("read_dir call failed");
     for entry in seed_dir {
         if let Ok(entry) = entry {
@@ -22,25 +94,81 @@ pub fn sync_depot(executor: &mut Executor, running: Arc<AtomicBool>, dir: &Path)
             if path.is_file() {
                 let file_len =
                     fs::metadata(path).expect("Could not fetch metadata.").len() as usize;
-                if file_len < config::MAX_INPUT_LEN {
+                let max_len = executor.cmd.max_len;
+                if file_len <= max_len || truncate {
                     let buf = read_from_file(path);
-                    if let Some(rawbuf) = buf {
-                        executor.run_norun(&rawbuf);
+                    if let Some(mut rawbuf) = buf {
+                        if rawbuf.len() > max_len {
+                            rawbuf.truncate(max_len);
+                            stats.num_truncated += 1;
+                        }
+                        let prov = Provenance::imported("seed", &path.to_string_lossy());
+                        stats.run(executor, &rawbuf, prov);
+                    } else {
+                        stats.num_errors += 1;
                     }
                 } else {
                     warn!("Seed discarded, too long: {:?}", path);
+                    stats.num_too_long += 1;
                 }
             }
         }
     }
+    info!("Imported seeds from {:?}: {}", dir, stats);
+}
+
+// The next id to import from each directory, also written to .synced/ in
+// the output directory like AFL does, so that a restart does not import
+// everything again. For watched directories it is a modification time.
+pub struct SyncIds {
+    synced_dir: PathBuf,
+    ids: HashMap<String, usize>,
 }
 
-// Now we are in a sub-dir of AFL's output dir
+impl SyncIds {
+    pub fn new(out_dir: &Path) -> Self {
+        let synced_dir = out_dir.join(defs::SYNCED_DIR);
+        fs::create_dir_all(&synced_dir).expect("Could not create .synced directory.");
+        Self {
+            synced_dir,
+            ids: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> usize {
+        if let Some(&id) = self.ids.get(key) {
+            return id;
+        }
+        // a u32, as in AFL
+        let id = fs::read(self.synced_dir.join(key))
+            .ok()
+            .and_then(|buf| (&buf[..]).read_u32::<LittleEndian>().ok())
+            .unwrap_or(0) as usize;
+        self.ids.insert(key.to_string(), id);
+        id
+    }
+
+    fn set(&mut self, key: &str, id: usize) {
+        if self.ids.insert(key.to_string(), id) == Some(id) {
+            return;
+        }
+        let mut buf = vec![];
+        buf.write_u32::<LittleEndian>(id as u32).unwrap();
+        let path = self.synced_dir.join(key);
+        if let Err(e) = fs::write(&path, buf) {
+            warn!("Could not save sync position {:?}: {:?}", path, e);
+        }
+    }
+}
+
+// Now we are in a sub-dir of AFL's output dir, named `instance`. Every other
+// fuzzer there is a peer, its queue and its crashes are imported.
 pub fn sync_afl(
     executor: &mut Executor,
     running: Arc<AtomicBool>,
     sync_dir: &Path,
-    sync_ids: &mut HashMap<String, usize>,
+    instance: &str,
+    sync_ids: &mut SyncIds,
 ) {
     //executor.rebind_forksrv();
 
@@ -51,12 +179,17 @@ pub fn sync_afl(
                 if entry_path.is_dir() {
                     let file_name = entry.file_name().into_string();
                     if let Ok(name) = file_name {
-                        if !name.contains(defs::ANGORA_DIR_NAME) && !name.starts_with(".") {
-                            let path = entry_path.join("queue");
-                            let is_main_path = entry_path.join("is_main_node");
-                            if path.is_dir() && is_main_path.is_file() {
+                        if name != instance && !name.starts_with(".") {
+                            let path = entry_path.join(defs::INPUTS_DIR);
+                            if path.is_dir() {
                                 sync_one_afl_dir(executor, running.clone(), &path, &name, sync_ids);
                             }
+                            // AFL's names have no '.', so the keys do not clash
+                            let path = entry_path.join(defs::CRASHES_DIR);
+                            if path.is_dir() {
+                                let key = format!("{}.{}", name, defs::CRASHES_DIR);
+                                sync_one_afl_dir(executor, running.clone(), &path, &key, sync_ids);
+                            }
                         }
                     }
                 }
@@ -65,28 +198,16 @@ pub fn sync_afl(
     }
 }
 
-fn get_afl_id(f: &fs::DirEntry) -> Option<usize> {
-    let file_name = f.file_name().into_string();
-    if let Ok(name) = file_name {
-        if name.len() >= 9 {
-            let id_str = &name[3..9];
-            if let Ok(id) = id_str.parse::<usize>() {
-                return Some(id);
-            }
-        }
-    }
-    None
-}
-
 fn sync_one_afl_dir(
     executor: &mut Executor,
     running: Arc<AtomicBool>,
     sync_dir: &Path,
     sync_name: &str,
-    sync_ids: &mut HashMap<String, usize>,
+    sync_ids: &mut SyncIds,
 ) {
-    let min_id = *sync_ids.get(sync_name).unwrap_or(&0);
+    let min_id = sync_ids.get(sync_name);
     let mut max_id = min_id;
+    let mut stats = ImportStats::default();
     let seed_dir = sync_dir
         .read_dir()
         .expect("read_dir call failed while syncing afl ..");
@@ -97,18 +218,23 @@ fn sync_one_afl_dir(
             }
             let path = &entry.path();
             if path.is_file() {
-                if let Some(id) = get_afl_id(&entry) {
+                if let Some(id) = parse_file_id(&entry.file_name().to_string_lossy()) {
                     if id >= min_id {
                         let file_len = fs::metadata(path).unwrap().len() as usize;
-                        if file_len < config::MAX_INPUT_LEN {
+                        if file_len <= executor.cmd.max_len {
                             info!("sync {:?}", path);
                             let buf = read_from_file(path);
                             if let Some(rawbuf) = buf {
-                                executor.run_norun(&rawbuf);
+                                let prov = Provenance::imported("sync", &path.to_string_lossy());
+                                stats.run(executor, &rawbuf, prov);
+                            } else {
+                                stats.num_errors += 1;
                             }
+                        } else {
+                            stats.num_too_long += 1;
                         }
-                        if id > max_id {
-                            max_id = id;
+                        if id + 1 > max_id {
+                            max_id = id + 1;
                         }
                     }
                 }
@@ -116,5 +242,177 @@ fn sync_one_afl_dir(
         }
     }
 
-    sync_ids.insert(sync_name.to_string(), max_id + 1);
+    if stats.total() > 0 {
+        info!("Synced from {}: {}", sync_name, stats);
+    }
+    sync_ids.set(sync_name, max_id);
+}
+
+// Seconds since the epoch, 0 if unknown.
+fn mtime_secs(path: &Path) -> usize {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as usize)
+        .unwrap_or(0)
+}
+
+// `--watch DIR`: inputs that other tools write to a directory, e.g. libFuzzer,
+// honggfuzz, a grammar generator or CI, are imported as soon as they are
+// closed for writing or moved in, with the same coverage check as the sync.
+pub struct Watcher {
+    inotify: Option<Inotify>,
+    // the directory and its key in SyncIds
+    dirs: HashMap<WatchDescriptor, (PathBuf, String)>,
+}
+
+impl Watcher {
+    pub fn new(dirs: &[&str]) -> Self {
+        if dirs.is_empty() {
+            return Self {
+                inotify: None,
+                dirs: HashMap::new(),
+            };
+        }
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .expect("Could not initialize inotify.");
+        let mut watched = HashMap::new();
+        for dir in dirs {
+            let dir = fs::canonicalize(dir).expect("Could not find the watched directory.");
+            let flags = AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO;
+            let wd = inotify
+                .add_watch(&dir, flags)
+                .expect("Could not watch the directory.");
+            let key = format!("watch.{}", dir.to_string_lossy().replace('/', "_"));
+            info!("Watching {:?}", dir);
+            watched.insert(wd, (dir, key));
+        }
+        Self {
+            inotify: Some(inotify),
+            dirs: watched,
+        }
+    }
+
+    // Import what was written while we were not watching, e.g. before a
+    // restart. Files as old as the saved position are run again, the same
+    // bytes are not saved twice anyway.
+    pub fn scan(&self, executor: &mut Executor, running: Arc<AtomicBool>, sync_ids: &mut SyncIds) {
+        for (dir, key) in self.dirs.values() {
+            let min_mtime = sync_ids.get(key);
+            let mut files: Vec<(usize, PathBuf)> = match dir.read_dir() {
+                Ok(entries) => entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+                    .map(|e| (mtime_secs(&e.path()), e.path()))
+                    .filter(|(mtime, path)| *mtime >= min_mtime && path.is_file())
+                    .collect(),
+                Err(e) => {
+                    warn!("Could not read {:?}: {:?}", dir, e);
+                    continue;
+                }
+            };
+            files.sort();
+            let mut stats = ImportStats::default();
+            for (mtime, path) in &files {
+                if !running.load(Ordering::SeqCst) {
+                    return;
+                }
+                import_watched(executor, &mut stats, path);
+                sync_ids.set(key, *mtime);
+            }
+            if stats.total() > 0 {
+                info!("Imported from {:?}: {}", dir, stats);
+            }
+        }
+    }
+
+    // Wait up to `timeout` for new files and import them.
+    pub fn wait(
+        &self,
+        executor: &mut Executor,
+        running: Arc<AtomicBool>,
+        timeout: time::Duration,
+        sync_ids: &mut SyncIds,
+    ) {
+        let inotify = match self.inotify {
+            Some(inotify) => inotify,
+            None => {
+                thread::sleep(timeout);
+                return;
+            }
+        };
+        let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+        // EINTR, e.g. on SIGINT, just ends the wait early
+        if poll(&mut fds, timeout.as_millis() as i32).unwrap_or(0) <= 0 {
+            return;
+        }
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(_) => return,
+        };
+        let mut stats = ImportStats::default();
+        for event in events {
+            if !running.load(Ordering::SeqCst) {
+                break;
+            }
+            let (dir, key, name) = match (self.dirs.get(&event.wd), event.name) {
+                (Some((dir, key)), Some(name)) => (dir, key, name),
+                _ => continue,
+            };
+            // temporary files of rsync and the like
+            if name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = dir.join(name);
+            import_watched(executor, &mut stats, &path);
+            let mtime = mtime_secs(&path);
+            if mtime > sync_ids.get(key) {
+                sync_ids.set(key, mtime);
+            }
+        }
+        if stats.total() > 0 {
+            info!("Imported watched files: {}", stats);
+        }
+    }
+}
+
+fn import_watched(executor: &mut Executor, stats: &mut ImportStats, path: &Path) {
+    let file_len = match fs::metadata(path) {
+        Ok(m) => m.len() as usize,
+        Err(_) => {
+            stats.num_errors += 1;
+            return;
+        }
+    };
+    if file_len > executor.cmd.max_len {
+        stats.num_too_long += 1;
+        return;
+    }
+    match read_from_file(path) {
+        Some(buf) => {
+            let prov = Provenance::imported("watch", &path.to_string_lossy());
+            stats.run(executor, &buf, prov);
+        }
+        None => stats.num_errors += 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_ids() {
+        let out_dir = std::env::temp_dir().join(format!("fastgen_sync_{}", std::process::id()));
+        let mut ids = SyncIds::new(&out_dir);
+        assert_eq!(ids.get("fuzzer01"), 0);
+        ids.set("fuzzer01", 12);
+        ids.set("fuzzer01.crashes", 3);
+        // read back after a restart
+        let mut ids = SyncIds::new(&out_dir);
+        assert_eq!(ids.get("fuzzer01"), 12);
+        assert_eq!(ids.get("fuzzer01.crashes"), 3);
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
 }
@@ -0,0 +1,110 @@
+// Every saved input, with the coverage at that moment, appended to
+// coverage_timeline.csv in the output directory. Edges-over-time curves can
+// be plotted from it directly instead of replaying the queue like
+// usenix/aggregate_edge.sh does.
+
+use crate::{provenance::status_name, status_type::StatusType};
+use fastgen_common::defs;
+use std::{
+    fs::{File, OpenOptions},
+    io::prelude::*,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{self, SystemTime, UNIX_EPOCH},
+};
+
+static TIMELINE_HEADER: &str = "unix_time_ms,run_time_ms,id,status,stage,total_edges,new_edges";
+
+pub struct TimelineRecord {
+    pub id: usize,
+    pub status: StatusType,
+    // where the input came from, see Provenance
+    pub stage: &'static str,
+    // edges of the normal runs, like fuzzer_stats
+    pub total_edges: usize,
+    // edges the input hit first, in the map of its status
+    pub new_edges: usize,
+}
+
+impl TimelineRecord {
+    fn to_csv(&self, unix_time_ms: u128, run_time_ms: u128) -> String {
+        format!(
+            "{},{},{},{},{},{},{}",
+            unix_time_ms,
+            run_time_ms,
+            self.id,
+            status_name(self.status),
+            self.stage,
+            self.total_edges,
+            self.new_edges
+        )
+    }
+}
+
+pub struct Timeline {
+    start: time::Instant,
+    // ms of the earlier runs, the checkpoint keeps it
+    offset_ms: AtomicU64,
+    file: Mutex<File>,
+}
+
+impl Timeline {
+    // Appends to the file of an earlier run, e.g. when resuming, see resume_at.
+    pub fn new(out_dir: &Path) -> Self {
+        let path = out_dir.join(defs::TIMELINE_FILE);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .expect("Could not create timeline file.");
+        if file.metadata().map(|m| m.len() == 0).unwrap_or(false) {
+            writeln!(file, "{}", TIMELINE_HEADER).expect("Could not write timeline file.");
+        }
+        Self {
+            start: time::Instant::now(),
+            offset_ms: AtomicU64::new(0),
+            file: Mutex::new(file),
+        }
+    }
+
+    // The run time goes on from where the checkpoint left it.
+    pub fn resume_at(&self, run_time_ms: u64) {
+        self.offset_ms.store(run_time_ms, Ordering::Relaxed);
+    }
+
+    pub fn run_time_ms(&self) -> u64 {
+        self.offset_ms.load(Ordering::Relaxed) + self.start.elapsed().as_millis() as u64
+    }
+
+    pub fn record(&self, rec: &TimelineRecord) {
+        let unix_time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let line = rec.to_csv(unix_time_ms, self.run_time_ms() as u128);
+        let mut f = self.file.lock().unwrap();
+        if let Err(e) = writeln!(f, "{}", line) {
+            warn!("Could not write timeline: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_csv() {
+        let rec = TimelineRecord {
+            id: 12,
+            status: StatusType::Crash,
+            stage: "solver",
+            total_edges: 345,
+            new_edges: 2,
+        };
+        assert_eq!(rec.to_csv(1600000000123, 4567), "1600000000123,4567,12,crash,solver,345,2");
+    }
+}
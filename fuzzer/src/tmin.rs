@@ -0,0 +1,278 @@
+// `fastgen tmin`: shrink a crashing or hanging input, like afl-tmin.
+// A smaller input is kept only if it ends the same way, and for crashes by
+// the same signal. If a track binary is given, the input is tracked once and
+// the bytes no branch depends on are removed or zeroed first, before the
+// usual block deletion.
+
+use crate::{
+    branches::GlobalBranches,
+    command::CommandOpt,
+    depot::Depot,
+    executor::Executor,
+    fifo::read_pipe,
+    file::read_from_file,
+    op_def::{DFSAN_LOAD, DFSAN_READ},
+    scheduler::SchedulePolicy,
+    status_type::StatusType,
+    union_table::*,
+};
+use fastgen_common::config;
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+// How the target ended, the thing that must not change while shrinking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Outcome {
+    status: StatusType,
+    signal: Option<i32>,
+}
+
+fn run_once(executor: &mut Executor, buf: &Vec<u8>) -> Outcome {
+    let (status, _) = executor.run_calibrate(buf);
+    let status = match status {
+        // too many timeouts in a row, which is what we are after for hangs
+        StatusType::Skip => StatusType::Timeout,
+        s => s,
+    };
+    let signal = if status == StatusType::Crash {
+        executor.last_signal()
+    } else {
+        None
+    };
+    Outcome { status, signal }
+}
+
+// The input offsets a label depends on.
+fn collect_offsets(label: u32, table: &UnionTable, offsets: &mut HashSet<usize>) {
+    let mut seen = HashSet::new();
+    let mut stack = vec![label];
+    while let Some(label) = stack.pop() {
+        if label == 0 || label as usize >= table.len() || !seen.insert(label) {
+            continue;
+        }
+        let info = &table[label as usize];
+        let (l1, l2, op1) = (info.l1, info.l2, info.op1);
+        match info.op as u32 {
+            DFSAN_READ => {
+                offsets.insert(op1 as usize);
+            }
+            DFSAN_LOAD => {
+                let start = table[l1 as usize].op1 as usize;
+                offsets.extend(start..start + l2 as usize);
+            }
+            _ => {
+                stack.push(l1);
+                stack.push(l2);
+            }
+        }
+    }
+}
+
+// Track the input once and return the offsets that reach a branch, a GEP or
+// a memcmp on the way to the crash.
+fn tainted_offsets(executor: &mut Executor, buf: &Vec<u8>) -> HashSet<usize> {
+    let (child, read_end) = executor.track(0, buf);
+    let (msgs, _) = read_pipe(read_end);
+    let exit = executor.finish_track(child);
+    debug!("Tracked {} messages, {}", msgs.len(), exit);
+
+    let ptr = unsafe { libc::shmat(executor.shmid, std::ptr::null(), 0) as *mut UnionTable };
+    let table = unsafe { &*ptr };
+    let mut offsets = HashSet::new();
+    for msg in &msgs {
+        if msg.1 != std::u32::MAX {
+            collect_offsets(msg.1, table, &mut offsets);
+        }
+    }
+    unsafe { libc::shmdt(ptr as *const libc::c_void) };
+    offsets
+}
+
+// The [start, end) ranges of the offsets below `len` that are not tainted.
+fn untainted_ranges(len: usize, tainted: &HashSet<usize>) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    let mut start = None;
+    for i in 0..len {
+        match (tainted.contains(&i), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                ranges.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        ranges.push((s, len));
+    }
+    ranges
+}
+
+struct Minimizer {
+    executor: Executor,
+    target: Outcome,
+    num_execs: usize,
+}
+
+impl Minimizer {
+    fn keeps_outcome(&mut self, buf: &Vec<u8>) -> bool {
+        self.num_execs += 1;
+        run_once(&mut self.executor, buf) == self.target
+    }
+
+    // Remove the untainted ranges, or zero them if they cannot go.
+    // The last ones go first so that the earlier offsets stay valid.
+    fn strip_untainted(&mut self, buf: &mut Vec<u8>, ranges: &Vec<(usize, usize)>) {
+        for &(start, end) in ranges.iter().rev() {
+            let mut cand = buf.clone();
+            cand.drain(start..end);
+            if self.keeps_outcome(&cand) {
+                *buf = cand;
+                continue;
+            }
+            if buf[start..end].iter().all(|&b| b == 0) {
+                continue;
+            }
+            let mut cand = buf.clone();
+            cand[start..end].iter_mut().for_each(|b| *b = 0);
+            if self.keeps_outcome(&cand) {
+                *buf = cand;
+            }
+        }
+    }
+
+    // Delete blocks of halving size until nothing can be removed.
+    fn delete_blocks(&mut self, buf: &mut Vec<u8>) {
+        loop {
+            let len_before = buf.len();
+            let mut chunk = std::cmp::max(buf.len() / 2, 1);
+            while chunk > 0 {
+                let mut pos = 0;
+                while pos < buf.len() && buf.len() > 1 {
+                    let end = std::cmp::min(pos + chunk, buf.len());
+                    let mut cand = buf.clone();
+                    cand.drain(pos..end);
+                    if !cand.is_empty() && self.keeps_outcome(&cand) {
+                        *buf = cand;
+                    } else {
+                        pos += chunk;
+                    }
+                }
+                chunk /= 2;
+            }
+            if buf.len() == len_before {
+                break;
+            }
+        }
+    }
+}
+
+pub fn tmin_main(
+    input: &str,
+    output: &str,
+    track_target: Option<&str>,
+    pargs: Vec<String>,
+    mem_limit: u64,
+    time_limit: u64,
+) {
+    pretty_env_logger::init();
+
+    let buf = read_from_file(Path::new(input)).expect("Could not read the input.");
+    // the forkserver files and the depot of the executor go here
+    let pid = unsafe { libc::getpid() as usize };
+    let work_dir = env::temp_dir().join(format!("fastgen_tmin_{}", pid));
+    fs::create_dir(&work_dir).expect("Could not create work directory.");
+
+    let track = track_target.map(|t| t.to_string());
+    let cmd = CommandOpt::new(
+        track.as_ref().unwrap_or(&pargs[0]),
+        pargs.clone(),
+        &work_dir,
+        mem_limit,
+        time_limit,
+        config::TIME_LIMIT_TRACK,
+        None,
+        config::MAX_INPUT_LEN,
+    );
+    let depot = Arc::new(Depot::new(
+        work_dir.clone(),
+        &work_dir,
+        SchedulePolicy::Fifo,
+    ));
+    let shmid = if track.is_some() {
+        unsafe {
+            libc::shmget(
+                libc::IPC_PRIVATE,
+                0xc00000000,
+                0o644 | libc::IPC_CREAT | libc::SHM_NORESERVE,
+            )
+        }
+    } else {
+        0
+    };
+    let mut executor = Executor::new(
+        cmd.specify(1),
+        Arc::new(GlobalBranches::new(config::BRANCHES_SIZE)),
+        depot,
+        shmid,
+        true,
+        Arc::new(Mutex::new(0)),
+    );
+
+    let target = run_once(&mut executor, &buf);
+    if target.status != StatusType::Crash && target.status != StatusType::Timeout {
+        error!("The input does not crash or hang: {:?}", target.status);
+    } else {
+        info!("Minimizing {} bytes, keeping {:?}", buf.len(), target);
+        let ranges = if track.is_some() {
+            let tainted = tainted_offsets(&mut executor, &buf);
+            info!("{} of {} bytes are tainted", tainted.len(), buf.len());
+            untainted_ranges(buf.len(), &tainted)
+        } else {
+            vec![]
+        };
+
+        let mut minimizer = Minimizer {
+            executor,
+            target,
+            num_execs: 0,
+        };
+        let mut min_buf = buf.clone();
+        minimizer.strip_untainted(&mut min_buf, &ranges);
+        minimizer.delete_blocks(&mut min_buf);
+
+        fs::write(output, &min_buf).expect("Could not write the output.");
+        info!(
+            "Shrunk {} to {} bytes in {} runs, wrote {}",
+            buf.len(),
+            min_buf.len(),
+            minimizer.num_execs,
+            output
+        );
+    }
+
+    if shmid != 0 {
+        unsafe { libc::shmctl(shmid, libc::IPC_RMID, std::ptr::null_mut()) };
+    }
+    drop(cmd);
+    if let Err(e) = fs::remove_dir_all(&work_dir) {
+        warn!("Could not remove {:?}: {:?}", work_dir, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_untainted_ranges() {
+        let tainted: HashSet<usize> = [0, 3, 4, 7].iter().cloned().collect();
+        assert_eq!(untainted_ranges(10, &tainted), vec![(1, 3), (5, 7), (8, 10)]);
+        assert_eq!(untainted_ranges(3, &HashSet::new()), vec![(0, 3)]);
+        assert!(untainted_ranges(0, &tainted).is_empty());
+    }
+}
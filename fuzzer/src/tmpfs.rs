@@ -10,7 +10,7 @@ static LINUX_TMPFS_DIR: &str = "/dev/shm";
 
 pub fn create_tmpfs_dir(target: &Path) {
     if env::var(defs::PERSIST_TRACK_FILES).is_ok() {
-        fs::create_dir(&target).unwrap();
+        fs::create_dir_all(&target).unwrap();
         return;
     }
     let shm_dir = Path::new(LINUX_TMPFS_DIR);
@@ -21,7 +21,8 @@ pub fn create_tmpfs_dir(target: &Path) {
         let dir_name = format!("angora_tmp_{}", pid);
         let tmp_dir = shm_dir.join(dir_name);
         fs::create_dir(&tmp_dir).unwrap();
-        if target.exists() {
+        // a stale link from the run we resume, even if it is dangling
+        if fs::symlink_metadata(target).is_ok() {
             fs::remove_file(target).unwrap();
         }
         symlink(&tmp_dir, target).unwrap();
@@ -31,7 +32,7 @@ pub fn create_tmpfs_dir(target: &Path) {
             "System does not have {} directory! Can't use tmpfs.",
             LINUX_TMPFS_DIR
         );
-        fs::create_dir(&target).unwrap();
+        fs::create_dir_all(&target).unwrap();
     }
 }
 
@@ -0,0 +1,95 @@
+// Outcome of tracking each seed, appended to track_stat.csv in the output
+// directory. Seeds that crash or hang under the taint-tracking build would
+// otherwise vanish silently.
+
+use crate::{watchdog::TrackExit, z3solver::SolveStats};
+use fastgen_common::defs;
+use std::{
+    fs::{File, OpenOptions},
+    io::prelude::*,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time,
+};
+
+static TRACK_STAT_HEADER: &str =
+    "id,code,signal,timed_out,killed,oom,time_ms,msgs,union_bytes,solve_capped,attempts,failures";
+
+pub struct TrackRecord {
+    pub id: usize,
+    pub exit: TrackExit,
+    pub time: time::Duration,
+    pub solve: SolveStats,
+    // tracking runs of this seed, including the retries after a failure
+    pub attempts: usize,
+    pub failures: usize,
+}
+
+impl TrackRecord {
+    pub fn always_fails(&self) -> bool {
+        self.failures > 0 && self.failures == self.attempts
+    }
+}
+
+fn opt_to_string(v: Option<i32>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_default()
+}
+
+pub struct TrackStats {
+    file: Mutex<File>,
+    pub num_tracked: AtomicUsize,
+    pub num_failed: AtomicUsize,
+    pub num_capped: AtomicUsize,
+}
+
+impl TrackStats {
+    pub fn new(out_dir: &Path) -> Self {
+        let path = out_dir.join(defs::TRACK_STAT_FILE);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .expect("Could not create track stat file.");
+        if file.metadata().map(|m| m.len() == 0).unwrap_or(false) {
+            writeln!(file, "{}", TRACK_STAT_HEADER).expect("Could not write track stat file.");
+        }
+        Self {
+            file: Mutex::new(file),
+            num_tracked: AtomicUsize::new(0),
+            num_failed: AtomicUsize::new(0),
+            num_capped: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn record(&self, rec: &TrackRecord) {
+        self.num_tracked.fetch_add(1, Ordering::Relaxed);
+        if rec.always_fails() {
+            self.num_failed.fetch_add(1, Ordering::Relaxed);
+        }
+        if rec.solve.time_capped {
+            self.num_capped.fetch_add(1, Ordering::Relaxed);
+        }
+        let line = format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            rec.id,
+            opt_to_string(rec.exit.code),
+            opt_to_string(rec.exit.signal),
+            rec.exit.timed_out as u8,
+            rec.exit.killed as u8,
+            rec.exit.oom as u8,
+            rec.time.as_millis(),
+            rec.solve.num_msgs,
+            rec.solve.union_table_bytes(),
+            rec.solve.time_capped as u8,
+            rec.attempts,
+            rec.failures
+        );
+        let mut f = self.file.lock().unwrap();
+        if let Err(e) = writeln!(f, "{}", line) {
+            warn!("Could not write track stat: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// A status screen like AFL's, redrawn in place and drawn from the same
+// counters as fuzzer_stats. When stdout is not a terminal, e.g. under nohup
+// or in CI, a one-line summary is logged every now and then instead.
+// The log goes to stderr, redirect it to keep it off the screen.
+
+use crate::{
+    branches::GlobalBranches,
+    depot::Depot,
+    fuzz_stats::{FuzzStats, Snapshot},
+    track_stats::TrackStats,
+};
+use fastgen_common::config;
+use std::{
+    fmt::Write as FmtWrite,
+    io::{self, prelude::*},
+    sync::Arc,
+};
+
+fn fmt_duration(secs: u64) -> String {
+    format!(
+        "{} days, {} hrs, {} min, {} sec",
+        secs / 86400,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// `then` is a unix time, 0 if it never happened
+fn fmt_ago(now: u64, then: u64) -> String {
+    if then == 0 {
+        "never".to_string()
+    } else {
+        fmt_duration(now.saturating_sub(then))
+    }
+}
+
+fn ratio(n: usize, total: usize) -> String {
+    format!("{:.2}%", n as f64 * 100.0 / std::cmp::max(total, 1) as f64)
+}
+
+fn render(snap: &Snapshot, banner: &str) -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, " fastgen -- {}", banner);
+    let _ = writeln!(s);
+    let _ = writeln!(s, " run time      : {}", fmt_duration(snap.run_time));
+    let _ = writeln!(s, " last new path : {}", fmt_ago(snap.now, snap.last_find));
+    let _ = writeln!(s, " last crash    : {}", fmt_ago(snap.now, snap.last_crash));
+    let _ = writeln!(s, " last hang     : {}", fmt_ago(snap.now, snap.last_hang));
+    let _ = writeln!(s);
+    let _ = writeln!(
+        s,
+        " corpus        : {} ({} new, {} imported, {} pending)",
+        snap.corpus_count,
+        snap.corpus_found(),
+        snap.imported,
+        snap.pending
+    );
+    let _ = writeln!(s, " crashes       : {}", snap.crashes);
+    let _ = writeln!(s, " hangs         : {}", snap.hangs);
+    let _ = writeln!(
+        s,
+        " density       : {:.2}% ({} edges), stability {:.2}%",
+        snap.density, snap.edges, snap.stability
+    );
+    let _ = writeln!(s);
+    let _ = writeln!(
+        s,
+        " solutions     : {} ({} queued for grading)",
+        snap.solutions,
+        snap.queued()
+    );
+    let _ = writeln!(
+        s,
+        " graded        : {} ({:.1}/sec), {:.1} execs/sec in total",
+        snap.graded,
+        snap.graded_per_sec(),
+        snap.execs_per_sec()
+    );
+    let _ = writeln!(
+        s,
+        " flipped       : {} ({})",
+        snap.flipped,
+        ratio(snap.flipped, snap.graded)
+    );
+    let _ = writeln!(
+        s,
+        " reached       : {} ({})",
+        snap.reached,
+        ratio(snap.reached, snap.graded)
+    );
+    let _ = writeln!(
+        s,
+        " not reached   : {} ({})",
+        snap.not_reached,
+        ratio(snap.not_reached, snap.graded)
+    );
+    let _ = writeln!(s);
+    let _ = writeln!(s, " tracking      : {} seeds done", snap.tracked);
+    for (tid, id, elapsed) in &snap.tracking {
+        let _ = writeln!(s, "   thread {:<4}: seed {}, {} sec", tid, id, elapsed.as_secs());
+    }
+    s
+}
+
+fn summary(snap: &Snapshot) -> String {
+    format!(
+        "{} s: corpus {} ({} pending), {} crashes, {} hangs, density {:.2}%, \
+         graded {} ({:.1}/sec, {} queued), flipped {}, reached {}, not reached {}, \
+         last new path {} s ago",
+        snap.run_time,
+        snap.corpus_count,
+        snap.pending,
+        snap.crashes,
+        snap.hangs,
+        snap.density,
+        snap.graded,
+        snap.graded_per_sec(),
+        snap.queued(),
+        ratio(snap.flipped, snap.graded),
+        ratio(snap.reached, snap.graded),
+        ratio(snap.not_reached, snap.graded),
+        if snap.last_find == 0 {
+            snap.run_time
+        } else {
+            snap.now.saturating_sub(snap.last_find)
+        }
+    )
+}
+
+pub struct StatusScreen {
+    banner: String,
+    is_tty: bool,
+    stats: Arc<FuzzStats>,
+    depot: Arc<Depot>,
+    global_branches: Arc<GlobalBranches>,
+    track_stats: Arc<TrackStats>,
+}
+
+impl StatusScreen {
+    pub fn new(
+        banner: &str,
+        stats: Arc<FuzzStats>,
+        depot: Arc<Depot>,
+        global_branches: Arc<GlobalBranches>,
+        track_stats: Arc<TrackStats>,
+    ) -> Self {
+        let is_tty = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+        if is_tty {
+            // clear the screen once, later draws only overwrite it
+            print!("\x1b[2J");
+        } else {
+            info!("stdout is not a terminal, logging a summary instead of the status screen");
+        }
+        Self {
+            banner: banner.to_string(),
+            is_tty,
+            stats,
+            depot,
+            global_branches,
+            track_stats,
+        }
+    }
+
+    // s between draws
+    pub fn interval(&self) -> u64 {
+        if self.is_tty {
+            config::TUI_INTERVAL
+        } else {
+            config::SUMMARY_INTERVAL
+        }
+    }
+
+    pub fn draw(&self) {
+        let snap = self
+            .stats
+            .snapshot(&self.depot, &self.global_branches, &self.track_stats);
+        if !self.is_tty {
+            info!("{}", summary(&snap));
+            return;
+        }
+        let mut out = String::from("\x1b[H");
+        for line in render(&snap, &self.banner).lines() {
+            // clear what is left of the previous, longer line
+            out.push_str(line);
+            out.push_str("\x1b[K\n");
+        }
+        out.push_str("\x1b[J");
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        let _ = handle.write_all(out.as_bytes());
+        let _ = handle.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fmt_duration() {
+        assert_eq!(fmt_duration(0), "0 days, 0 hrs, 0 min, 0 sec");
+        assert_eq!(fmt_duration(90061), "1 days, 1 hrs, 1 min, 1 sec");
+        assert_eq!(fmt_ago(100, 0), "never");
+        assert_eq!(fmt_ago(100, 40), "0 days, 0 hrs, 1 min, 0 sec");
+        assert_eq!(ratio(1, 3), "33.33%");
+        assert_eq!(ratio(0, 0), "0.00%");
+    }
+}
@@ -0,0 +1,216 @@
+// Supervise the tracking child without /usr/bin/timeout.
+// The child runs in its own process group (setsid), and the watchdog sends
+// SIGTERM and then SIGKILL to the whole group once the time limit expires.
+
+use fastgen_common::config;
+use nix::{
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+use std::{
+    fmt,
+    os::unix::process::ExitStatusExt,
+    process::Child,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread, time,
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrackExit {
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    // still running when we were done with it, e.g. the solver gave up reading
+    pub killed: bool,
+    // killed by the kernel for exceeding memory.max of its cgroup
+    pub oom: bool,
+}
+
+impl TrackExit {
+    pub fn is_failure(&self) -> bool {
+        self.timed_out || self.oom || (self.signal.is_some() && !self.killed)
+    }
+}
+
+impl fmt::Display for TrackExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.timed_out {
+            write!(f, "timeout")
+        } else if self.oom {
+            write!(f, "oom")
+        } else if self.killed {
+            write!(f, "killed")
+        } else if let Some(sig) = self.signal {
+            write!(f, "signal {}", sig)
+        } else if let Some(code) = self.code {
+            write!(f, "exit code {}", code)
+        } else {
+            write!(f, "unknown")
+        }
+    }
+}
+
+// Whether the child has exited, without reaping it, TrackChild::finish does.
+// The group of an unreaped leader does not go away, so killpg can not tell.
+fn has_exited(pid: Pid) -> bool {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid.as_raw() as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    };
+    // si_pid stays 0 while it runs
+    ret != 0 || unsafe { info.si_pid() } != 0
+}
+
+// SIGTERM the group, give the child some time, then SIGKILL whatever is left.
+pub fn kill_group(pgid: Pid) {
+    if killpg(pgid, Signal::SIGTERM).is_err() {
+        return;
+    }
+    let t_start = time::Instant::now();
+    while t_start.elapsed() < time::Duration::from_millis(config::TRACK_KILL_GRACE) {
+        if has_exited(pgid) {
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    let _ = killpg(pgid, Signal::SIGKILL);
+}
+
+struct Watchdog {
+    done: Arc<(Mutex<bool>, Condvar)>,
+    fired: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Watchdog {
+    fn start(pgid: Pid, time_limit: u64) -> Self {
+        let done = Arc::new((Mutex::new(false), Condvar::new()));
+        let fired = Arc::new(AtomicBool::new(false));
+        let d = done.clone();
+        let f = fired.clone();
+        let handle = thread::spawn(move || {
+            let deadline = time::Instant::now() + time::Duration::from_millis(time_limit);
+            let (lock, cvar) = &*d;
+            let mut finished = lock.lock().unwrap();
+            while !*finished {
+                let now = time::Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                finished = cvar.wait_timeout(finished, deadline - now).unwrap().0;
+            }
+            // killed with the lock held, so the child is not reaped meanwhile
+            // and its pgid can not be reused
+            if !*finished {
+                f.store(true, Ordering::SeqCst);
+                warn!("Tracking child {} timed out, killing its group", pgid);
+                kill_group(pgid);
+            }
+        });
+        Self {
+            done,
+            fired,
+            handle,
+        }
+    }
+
+    fn stop(self) -> bool {
+        {
+            let (lock, cvar) = &*self.done;
+            *lock.lock().unwrap() = true;
+            cvar.notify_one();
+        }
+        if self.handle.join().is_err() {
+            error!("Error happened in watchdog thread!");
+        }
+        self.fired.load(Ordering::SeqCst)
+    }
+}
+
+pub struct TrackChild {
+    child: Child,
+    watchdog: Watchdog,
+}
+
+impl TrackChild {
+    pub fn new(child: Child, time_limit: u64) -> Self {
+        let pgid = Pid::from_raw(child.id() as i32);
+        let watchdog = Watchdog::start(pgid, time_limit);
+        Self { child, watchdog }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    // Reap the child, killing its group first if it is still running.
+    // The watchdog is stopped before, it must not kill a reaped group.
+    pub fn finish(mut self) -> TrackExit {
+        let timed_out = self.watchdog.stop();
+        let pgid = Pid::from_raw(self.child.id() as i32);
+        let mut killed = false;
+        let status = match self.child.try_wait() {
+            Ok(Some(status)) => Ok(status),
+            Ok(None) => {
+                debug!("Tracking child {} still running, killing its group", pgid);
+                killed = true;
+                let _ = killpg(pgid, Signal::SIGTERM);
+                let t_start = time::Instant::now();
+                while t_start.elapsed() < time::Duration::from_millis(config::TRACK_KILL_GRACE) {
+                    if let Ok(Some(_)) = self.child.try_wait() {
+                        break;
+                    }
+                    thread::sleep(time::Duration::from_millis(10));
+                }
+                // also take down anything the child left behind in its group
+                let _ = killpg(pgid, Signal::SIGKILL);
+                self.child.wait()
+            }
+            Err(e) => Err(e),
+        };
+        match status {
+            Ok(status) => TrackExit {
+                code: status.code(),
+                signal: status.signal(),
+                timed_out,
+                killed,
+                oom: false,
+            },
+            Err(e) => {
+                warn!("Error attempting to wait tracking child: {}", e);
+                TrackExit {
+                    timed_out,
+                    killed,
+                    ..Default::default()
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_has_exited() {
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+        let pid = Pid::from_raw(child.id() as i32);
+        assert!(!has_exited(pid));
+        child.kill().unwrap();
+        while !has_exited(pid) {
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        // still there to be reaped
+        assert!(child.wait().unwrap().signal().is_some());
+    }
+}
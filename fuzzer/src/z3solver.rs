@@ -1,5 +1,8 @@
+use crate::branch_stats::{BranchStats, SolveOutcome};
+use crate::coordinate::Coordinator;
 use crate::cpp_interface::*;
 use crate::fifo::PipeMsg;
+use crate::fuzz_stats::FuzzStats;
 use crate::op_def::*;
 use crate::solution::*;
 use crate::union_find::*;
@@ -24,6 +27,21 @@ use std::{
 use z3::ast::Ast;
 use z3::{ast, Config, Context, Model, Solver};
 
+// What a single tracking run produced, as seen from the solver
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SolveStats {
+    pub num_msgs: usize,
+    pub max_label: u32,
+    pub time_capped: bool,
+}
+
+impl SolveStats {
+    // labels are allocated in order, so the largest one bounds the table usage
+    pub fn union_table_bytes(&self) -> usize {
+        self.max_label as usize * std::mem::size_of::<dfsan_label_info>()
+    }
+}
+
 #[derive(Clone)]
 pub struct BranchDep<'a> {
     pub cons_set: Vec<z3::ast::Dynamic<'a>>,
@@ -610,8 +628,12 @@ pub fn solve_gep<'a>(
     uf: &mut UnionFind,
     branch_deps: &mut Vec<Option<BranchDep<'a>>>,
     fmemcmp_data: &HashMap<u32, Vec<u8>>,
-) -> (Option<HashMap<u32, u8>>, Option<HashMap<u32, u8>>) {
-    let mut ret = (None, None);
+) -> (
+    Option<HashMap<u32, u8>>,
+    Option<HashMap<u32, u8>>,
+    Option<SolveOutcome>,
+) {
+    let mut ret = (None, None, None);
     if label == 0 {
         return ret;
     }
@@ -633,7 +655,9 @@ pub fn solve_gep<'a>(
 
         let v0 = union(uf, &deps) as usize;
 
-        if try_solve {
+        if !try_solve {
+            ret.2 = Some(SolveOutcome::Skipped);
+        } else {
             if cond.as_bv().is_none() {
                 error!("condition must be a bv for gep");
                 return ret;
@@ -645,6 +669,7 @@ pub fn solve_gep<'a>(
             ));
             debug!("{:}", solver);
             let mut res = solver.check();
+            ret.2 = Some(SolveOutcome::from(res));
             if res == z3::SatResult::Sat {
                 debug!("sat opt");
                 let m = solver.get_model().unwrap();
@@ -680,11 +705,15 @@ pub fn solve_cond<'a>(
     uf: &mut UnionFind,
     branch_deps: &mut Vec<Option<BranchDep<'a>>>,
     fmemcmp_data: &HashMap<u32, Vec<u8>>,
-) -> (Option<HashMap<u32, u8>>, Option<HashMap<u32, u8>>) {
+) -> (
+    Option<HashMap<u32, u8>>,
+    Option<HashMap<u32, u8>>,
+    Option<SolveOutcome>,
+) {
     let result = z3::ast::Bool::from_bool(ctx, direction == 1);
     let result_bv = z3::ast::BV::from_i64(ctx, direction as i64, 1);
 
-    let mut ret = (None, None);
+    let mut ret = (None, None, None);
     if label == 0 {
         return ret;
     }
@@ -702,7 +731,9 @@ pub fn solve_cond<'a>(
 
         let v0 = union(uf, &deps) as usize;
 
-        if try_solve {
+        if !try_solve {
+            ret.2 = Some(SolveOutcome::Skipped);
+        } else {
             solver.reset();
             if cond.as_bool().is_none() {
                 solver.assert(&z3::ast::Dynamic::distinct(
@@ -716,6 +747,7 @@ pub fn solve_cond<'a>(
                 ));
             }
             let mut res = solver.check();
+            ret.2 = Some(SolveOutcome::from(res));
             if res == z3::SatResult::Sat {
                 debug!("sat opt");
                 let m = solver.get_model().unwrap();
@@ -793,8 +825,11 @@ pub fn solve(
     branch_gencount: &Arc<RwLock<HashMap<(u64, u64, u32, u64), u32>>>,
     branch_fliplist: &Arc<RwLock<HashSet<(u64, u64, u32, u64)>>>,
     branch_hitcount: &Arc<RwLock<HashMap<(u64, u64, u32, u64), u32>>>,
-    solver_timeout: u64 // sec
-) {
+    solver_timeout: u64, // sec
+    fuzz_stats: &FuzzStats,
+    branch_stats: &BranchStats,
+    coordinator: Option<&Coordinator>,
+) -> SolveStats {
     info!("solve shmid {} and pipefd {}", shmid, pipefd);
     let rawptr = unsafe { libc::shmat(shmid, std::ptr::null(), 0) };
     let ptr = unsafe { rawptr as *mut UnionTable };
@@ -811,9 +846,14 @@ pub fn solve(
     let mut reader = BufReader::new(f);
     let t_start = time::Instant::now();
     let mut branch_local = HashMap::<(u64, u64), u32>::new();
+    let mut stats = SolveStats::default();
     loop {
         let rawmsg = PipeMsg::from_reader(&mut reader);
         if let Ok(msg) = rawmsg {
+            stats.num_msgs += 1;
+            if msg.label != std::u32::MAX && msg.label > stats.max_label {
+                stats.max_label = msg.label;
+            }
             let mut hitcount = 1;
             let mut gencount = 0;
             let mut flipped = false;
@@ -858,6 +898,13 @@ pub fn solve(
                 flipped = true;
             }
 
+            // the hits and flips of the other instances count too
+            if let Some(coord) = coordinator {
+                let key = (msg.addr, msg.ctx, localcnt, msg.result);
+                hitcount = std::cmp::max(hitcount, coord.hit(&key));
+                flipped |= coord.is_flipped(&key);
+            }
+
             if branch_gencount
                 .read()
                 .unwrap()
@@ -872,6 +919,12 @@ pub fn solve(
 
             if msg.msgtype == 0 {
                 if localcnt > 64 {
+                    branch_stats.solved(
+                        (msg.addr, msg.ctx, localcnt, msg.result),
+                        msg.bid,
+                        SolveOutcome::Skipped,
+                        0,
+                    );
                     continue;
                 }
                 let try_solve = if config::QSYM_FILTER {
@@ -879,6 +932,7 @@ pub fn solve(
                 } else {
                     hitcount <= 5 && (!flipped) && localcnt <= 16
                 };
+                let t_solve = time::Instant::now();
                 let rawsol = solve_cond(
                     msg.label,
                     msg.result,
@@ -890,6 +944,17 @@ pub fn solve(
                     &mut branch_deps,
                     &fmemcmp_data,
                 );
+                let solve_time = t_solve.elapsed();
+                fuzz_stats.solved(solve_time);
+                if let Some(outcome) = rawsol.2 {
+                    let num_solutions = rawsol.0.is_some() as usize + rawsol.1.is_some() as usize;
+                    branch_stats.solved(
+                        (msg.addr, msg.ctx, localcnt, msg.result),
+                        msg.bid,
+                        outcome,
+                        num_solutions,
+                    );
+                }
                 if let Some(sol) = rawsol.0 {
                     let sol_size = sol.len();
                     let rgd_sol = Solution::new(
@@ -906,7 +971,11 @@ pub fn solve(
                         true,
                         msg.predicate,
                         msg.target_cond,
+                        msg.msgtype,
+                        false,
+                        solve_time,
                     );
+                    fuzz_stats.num_solutions.fetch_add(1, Ordering::Relaxed);
                     solution_queue.push(rgd_sol);
                 }
                 if let Some(sol) = rawsol.1 {
@@ -925,15 +994,26 @@ pub fn solve(
                         true,
                         msg.predicate,
                         msg.target_cond,
+                        msg.msgtype,
+                        true,
+                        solve_time,
                     );
+                    fuzz_stats.num_solutions.fetch_add(1, Ordering::Relaxed);
                     solution_queue.push(rgd_sol);
                 }
             } else if msg.msgtype == 1 {
                 //gep
                 if localcnt > 64 {
+                    branch_stats.solved(
+                        (msg.addr, msg.ctx, localcnt, msg.result),
+                        msg.bid,
+                        SolveOutcome::Skipped,
+                        0,
+                    );
                     continue;
                 }
                 let try_solve = hitcount <= 5 && localcnt <= 16;
+                let t_solve = time::Instant::now();
                 let rawsol = solve_gep(
                     msg.label,
                     msg.result,
@@ -945,20 +1025,33 @@ pub fn solve(
                     &mut branch_deps,
                     &fmemcmp_data,
                 );
+                let solve_time = t_solve.elapsed();
+                fuzz_stats.solved(solve_time);
+                if let Some(outcome) = rawsol.2 {
+                    let num_solutions = rawsol.0.is_some() as usize + rawsol.1.is_some() as usize;
+                    branch_stats.solved(
+                        (msg.addr, msg.ctx, localcnt, msg.result),
+                        msg.bid,
+                        outcome,
+                        num_solutions,
+                    );
+                }
                 if let Some(sol) = rawsol.0 {
                     let sol_size = sol.len();
                     let rgd_sol = Solution::new(
                         sol, msg.tid, msg.addr, msg.ctx, localcnt, msg.result, 0, sol_size,
-                        msg.bid, msg.sctx, false, 0, 0,
+                        msg.bid, msg.sctx, false, 0, 0, msg.msgtype, false, solve_time,
                     );
+                    fuzz_stats.num_solutions.fetch_add(1, Ordering::Relaxed);
                     solution_queue.push(rgd_sol);
                 }
                 if let Some(sol) = rawsol.1 {
                     let sol_size = sol.len();
                     let rgd_sol = Solution::new(
                         sol, msg.tid, msg.addr, msg.ctx, localcnt, msg.result, 0, sol_size,
-                        msg.bid, msg.sctx, false, 0, 0,
+                        msg.bid, msg.sctx, false, 0, 0, msg.msgtype, true, solve_time,
                     );
+                    fuzz_stats.num_solutions.fetch_add(1, Ordering::Relaxed);
                     solution_queue.push(rgd_sol);
                 }
             } else if msg.msgtype == 2 {
@@ -1002,7 +1095,8 @@ pub fn solve(
                 //size
             }
             debug!("solving eplased {}", t_start.elapsed().as_secs());
-            if t_start.elapsed().as_secs() > 90 {
+            if t_start.elapsed().as_secs() > config::MAX_SOLVE_TIME {
+                stats.time_capped = true;
                 break;
             }
         } else {
@@ -1011,4 +1105,5 @@ pub fn solve(
     }
     unsafe { end_session(session); }
     unsafe { libc::shmdt(rawptr) };
+    stats
 }
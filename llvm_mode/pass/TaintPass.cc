@@ -87,6 +87,13 @@ using namespace llvm;
 static const char *const kTaintExternShadowPtrMask = "__dfsan_shadow_ptr_mask";
 
 static cl::opt<bool> TrackMode("TrackMode", cl::desc("track mode"), cl::Hidden);
+// Same as OUTPUT_COND_LOC_VAR in defs.h, which is not included here.
+// The ids printed here are the bids that fastgen reports.
+static const char *const kOutputCondLocVar = "ANGORA_OUTPUT_COND_LOC";
+// Edge ids are drawn below the largest map size (MAP_SIZE_POW2_MAX in
+// config.rs) and masked with __angora_map_mask, which the runtime sets to the
+// size the fuzzer chose.
+static const uint32_t kMapSizeMax = 1 << 24;
 // The -taint-preserve-alignment flag controls whether this pass assumes that
 // alignment requirements provided by the input IR are correct.  For example,
 // if the input IR contains a load with alignment 8, this flag will cause
@@ -361,6 +368,9 @@ class Taint : public ModulePass {
   Constant *AngoraContext;
   Constant *AngoraPrevLoc;
   Constant *AngoraMapPtr;
+  Constant *AngoraMapMask;
+  Constant *AngoraCtxMask;
+  Constant *AngoraPrevShift;
   MDNode *ColdCallWeights;
   TaintABIList ABIList;
   DenseMap<Value *, Function *> UnwrappedFnMap;
@@ -633,7 +643,7 @@ void Taint::addContextRecording(Function &F) {
   size_t pos = F.getName().find_first_of("$");
   std::string fnNameStripped = F.getName().substr(pos+1, F.getName().size() - pos - 1);
 
-  uint32_t rr = hashCallName(fnNameStripped, ModName) % 1048576;
+  uint32_t rr = hashCallName(fnNameStripped, ModName) % kMapSizeMax;
 
   Constant* rrv = ConstantInt::get(Int32Ty, rr);
 
@@ -879,7 +889,7 @@ Constant *Taint::getOrBuildTrampolineFunction(FunctionType *FT,
   return C;
 }
 
-uint32_t Taint::getRandomBasicBlockId() { return random() % 1048576; }
+uint32_t Taint::getRandomBasicBlockId() { return random() % kMapSizeMax; }
 
 void Taint::countEdge(BasicBlock &BB) {
   //if (TrackMode || skipBasicBlock()) {
@@ -903,8 +913,13 @@ void Taint::countEdge(BasicBlock &BB) {
   LoadInst *MapPtr = IRB.CreateLoad(AngoraMapPtr);
   setInsNonSan(MapPtr);
 
+  LoadInst *MapMask = IRB.CreateLoad(AngoraMapMask);
+  setInsNonSan(MapMask);
+
   Value *BrId = IRB.CreateXor(PrevLocCasted, CurLoc);
   setValueNonSan(BrId);
+  BrId = IRB.CreateAnd(BrId, MapMask);
+  setValueNonSan(BrId);
   Value *MapPtrIdx = IRB.CreateGEP(MapPtr, BrId);
   setValueNonSan(MapPtrIdx);
 
@@ -933,9 +948,24 @@ void Taint::countEdge(BasicBlock &BB) {
 
   Value *CtxValCasted = IRB.CreateZExt(CtxVal, Int32Ty);
   setValueNonSan(CtxValCasted);
+
+  // The coverage mode of the fuzzer picks the masks, see coverage_mode.rs:
+  // PrevLoc = (PrevLoc >> PrevShift) ^ (Ctx & CtxMask) ^ (cur_loc >> 1)
+  LoadInst *CtxMask = IRB.CreateLoad(AngoraCtxMask);
+  setInsNonSan(CtxMask);
+  Value *CtxPart = IRB.CreateAnd(CtxValCasted, CtxMask);
+  setValueNonSan(CtxPart);
+
+  LoadInst *PrevShift = IRB.CreateLoad(AngoraPrevShift);
+  setInsNonSan(PrevShift);
+  Value *History = IRB.CreateLShr(PrevLocCasted, PrevShift);
+  setValueNonSan(History);
+
     // Udate PrevLoc
+  NewPrevLoc = IRB.CreateXor(History, CtxPart);
+  setValueNonSan(NewPrevLoc);
   NewPrevLoc =
-      IRB.CreateXor(CtxValCasted, ConstantInt::get(Int32Ty, cur_loc >> 1));
+      IRB.CreateXor(NewPrevLoc, ConstantInt::get(Int32Ty, cur_loc >> 1));
   setValueNonSan(NewPrevLoc);
 
   StoreInst *Store = IRB.CreateStore(NewPrevLoc, AngoraPrevLoc);
@@ -1064,6 +1094,18 @@ bool Taint::runOnModule(Module &M) {
     AngoraMapPtr = new GlobalVariable(M, PointerType::get(Int8Ty, 0), false,
                                       GlobalValue::ExternalLinkage, 0,
                                       "__angora_area_ptr");
+
+    AngoraMapMask = new GlobalVariable(M, Int32Ty, false,
+                                       GlobalValue::ExternalLinkage, 0,
+                                       "__angora_map_mask");
+
+    AngoraCtxMask = new GlobalVariable(M, Int32Ty, false,
+                                       GlobalValue::ExternalLinkage, 0,
+                                       "__angora_ctx_mask");
+
+    AngoraPrevShift = new GlobalVariable(M, Int32Ty, false,
+                                         GlobalValue::ExternalLinkage, 0,
+                                         "__angora_prev_shift");
   }
 
   std::vector<Function *> FnsToInstrument;
@@ -1652,6 +1694,15 @@ uint32_t Taint::getInstructionId(Instruction *Inst) {
   
   UniqCidSet.insert(h);
 
+  if (getenv(kOutputCondLocVar)) {
+    errs() << "[ID] " << h << "\n";
+    if (Loc) {
+      errs() << "[LOC] " << cast<DIScope>(Loc->getScope())->getFilename()
+             << ", Ln " << Loc->getLine() << ", Col " << Loc->getColumn()
+             << "\n";
+    }
+  }
+
   return h;
 }
 
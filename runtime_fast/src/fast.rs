@@ -7,6 +7,7 @@ static START: Once = Once::new();
 #[ctor]
 fn fast_init() {
   START.call_once(|| {
+      shm_branches::set_coverage_mode();
       shm_branches::map_branch_counting_shm();
       forkcli::start_forkcli();
       });
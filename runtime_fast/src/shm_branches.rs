@@ -1,7 +1,8 @@
 // map branch counting shared memory.
 
-use fastgen_common::config::BRANCHES_SIZE;
-use fastgen_common::defs::BRANCHES_SHM_ENV_VAR;
+use fastgen_common::config::{BRANCHES_SIZE, MAP_SIZE_POW2_MAX, MAP_SIZE_POW2_MIN};
+use fastgen_common::coverage_mode::CoverageMode;
+use fastgen_common::defs::{BRANCHES_SHM_ENV_VAR, COVERAGE_MODE_ENV_VAR, MAP_SIZE_ENV_VAR};
 use fastgen_common::shm;
 use std::env;
 use std::process;
@@ -12,18 +13,55 @@ static mut __ANGORA_AREA_INITIAL: BranchBuf = [255; BRANCHES_SIZE];
 #[no_mangle]
 pub static mut __angora_area_ptr: *const u8 = unsafe{  &__ANGORA_AREA_INITIAL[0] as *const u8 };
 
+// The pass ANDs every edge id with this, it fits the initial area until the
+// fuzzer's map is attached.
+#[no_mangle]
+pub static mut __angora_map_mask: u32 = (BRANCHES_SIZE - 1) as u32;
+
+// How the previous location is kept, see coverage_mode.rs. The defaults are
+// the context mode.
+#[no_mangle]
+pub static mut __angora_ctx_mask: u32 = !0;
+#[no_mangle]
+pub static mut __angora_prev_shift: u32 = 24;
+
+pub fn set_coverage_mode() {
+    if let Ok(val) = env::var(COVERAGE_MODE_ENV_VAR) {
+        let mode = val.parse::<CoverageMode>().expect("Could not parse coverage mode.");
+        unsafe {
+            __angora_ctx_mask = mode.ctx_mask();
+            __angora_prev_shift = mode.prev_shift();
+        }
+    }
+}
+
+// The size of the fuzzer's map, the default if it is not set.
+fn map_size() -> usize {
+    let size = match env::var(MAP_SIZE_ENV_VAR) {
+        Ok(val) => val.parse::<usize>().expect("Could not parse map size."),
+        Err(_) => return BRANCHES_SIZE,
+    };
+    if !size.is_power_of_two() || size < 1 << MAP_SIZE_POW2_MIN || size > 1 << MAP_SIZE_POW2_MAX {
+        eprintln!("bad map size {}", size);
+        process::exit(1);
+    }
+    size
+}
+
 pub fn map_branch_counting_shm() {
     let id_val = env::var(BRANCHES_SHM_ENV_VAR);
     match id_val {
         Ok(val) => {
             let shm_id = val.parse::<i32>().expect("Could not parse i32 value.");
-            let mem = shm::SHM::<BranchBuf>::from_id(shm_id);
+            let size = map_size();
+            let mem = shm::SHM::<u8>::from_id(shm_id);
             if mem.is_fail() {
               eprintln!("fail to load shm");
               process::exit(1);
             }
             unsafe {
                 __angora_area_ptr = mem.get_ptr() as *const u8;
+                __angora_map_mask = (size - 1) as u32;
             }
             return;
         }